@@ -0,0 +1,2 @@
+" Filetype detection for burne rename buffers.
+autocmd BufRead,BufNewFile *.burne setfiletype burne
@@ -0,0 +1,19 @@
+" Filetype plugin for burne rename buffers.
+
+if exists('b:did_ftplugin')
+  finish
+endif
+let b:did_ftplugin = 1
+
+" Each line is a filename, so never wrap or reformat lines.
+setlocal nowrap
+setlocal textwidth=0
+setlocal formatoptions-=t formatoptions-=c
+" Keep the buffer byte-exact: no BOM, no CRLF, and a final newline.
+setlocal nobomb
+setlocal fileformat=unix
+setlocal fixendofline
+" TAB separates the source and the new name in `--format pairs`.
+setlocal noexpandtab
+
+let b:undo_ftplugin = 'setlocal wrap< textwidth< formatoptions< bomb< fileformat< fixendofline< expandtab<'
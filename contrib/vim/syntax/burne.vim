@@ -0,0 +1,24 @@
+" Syntax definition for burne rename buffers.
+
+if exists('b:current_syntax')
+  finish
+endif
+
+" Percent-encoded sequences (`--escape percent` and `--escape percent-ascii`).
+syntax match burneEscape /%\x\x/
+" Invalid percent sequences.
+syntax match burneInvalidEscape /%\(\x\x\)\@!/
+" Marker of escaped names (`--escape auto`).
+syntax match burneEscapeMarker /\(^\|\t\)\@<=\\/
+" Separator of the source and the new name (`--format pairs`).
+syntax match burnePairSeparator /\t/
+" Whitespaces at the start or the end of names, which are easy to overlook.
+syntax match burneSurroundingSpace /^\s\+\|\s\+$/
+
+highlight default link burneEscape Special
+highlight default link burneEscapeMarker SpecialChar
+highlight default link burneInvalidEscape Error
+highlight default link burneSurroundingSpace Error
+highlight default link burnePairSeparator Delimiter
+
+let b:current_syntax = 'burne'
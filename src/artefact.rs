@@ -0,0 +1,153 @@
+//! Detection and normalization of artefacts added by editors.
+//!
+//! Some editors and clipboard round-trips add a UTF-8 BOM or CRLF line
+//! endings, strip or add the final newline, or leave trailing whitespaces.
+//! They should not end up in new filenames silently.
+
+use anyhow::bail;
+use burne::{EditFormat, LineSeparator};
+
+/// UTF-8 byte order mark.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Carriage return.
+const CR: u8 = b'\r';
+
+/// Normalizes the edited buffer.
+///
+/// `original` is the buffer written by `RenameSetup::write`, and `edited` is
+/// the buffer modified by the user.
+///
+/// Artefacts the original buffer could not have produced are removed.
+/// Artefacts that are ambiguous are reported as an error.
+pub(crate) fn normalize(
+    original: &[u8],
+    mut edited: Vec<u8>,
+    line_sep: LineSeparator,
+    format: EditFormat,
+) -> anyhow::Result<Vec<u8>> {
+    let sep = line_sep.to_byte();
+
+    // UTF-8 BOM.
+    if edited.starts_with(UTF8_BOM) && !original.starts_with(UTF8_BOM) {
+        log::warn!("removing UTF-8 BOM added to the edited file");
+        edited.drain(..UTF8_BOM.len());
+    }
+
+    match line_sep {
+        LineSeparator::LineFeed => edited = normalize_crlf(original, edited)?,
+        LineSeparator::Null => {
+            // Editors may add a final newline after the last NUL.
+            // The original buffer never has an unterminated line.
+            if edited.ends_with(&[sep, b'\n']) {
+                log::warn!("removing a newline added after the last NUL separator");
+                edited.pop();
+            }
+        }
+    }
+
+    // Missing final line separator.
+    if !edited.is_empty() && !edited.ends_with(&[sep]) {
+        log::debug!("adding the missing final line separator");
+        edited.push(sep);
+    }
+
+    check_trailing_whitespaces(original, &edited, sep, format)?;
+
+    Ok(edited)
+}
+
+/// Removes CR characters added by CRLF line endings.
+///
+/// Fails if the original buffer also has lines ending with CR, since it is
+/// ambiguous whether CRs are parts of the filenames.
+fn normalize_crlf(original: &[u8], edited: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let cr_lines = lines(&edited, b'\n')
+        .enumerate()
+        .filter(|(_, line)| line.last() == Some(&CR))
+        // Line numbers are 1-based.
+        .map(|(index, _)| index + 1)
+        .collect::<Vec<_>>();
+    if cr_lines.is_empty() {
+        return Ok(edited);
+    }
+
+    if lines(original, b'\n').any(|line| line.last() == Some(&CR)) {
+        bail!(
+            "lines {:?} end with a carriage return (CR), which may be a part of \
+             a filename or may come from CRLF line endings. \
+             use `--escape auto` or `--escape percent` to disambiguate",
+            cr_lines
+        );
+    }
+
+    log::warn!(
+        "removing carriage returns (CR) at the end of lines {:?}, \
+         possibly added by CRLF line endings",
+        cr_lines
+    );
+    let mut normalized = Vec::with_capacity(edited.len());
+    for line in lines(&edited, b'\n') {
+        normalized.extend_from_slice(line.strip_suffix(&[CR]).unwrap_or(line));
+        normalized.push(b'\n');
+    }
+    if !edited.ends_with(b"\n") {
+        // Keep the missing final newline missing.
+        normalized.pop();
+    }
+
+    Ok(normalized)
+}
+
+/// Fails if changed destinations have trailing whitespaces.
+///
+/// Trailing whitespaces are easy to overlook in editors, and filenames
+/// intentionally ending with whitespaces can still be written by escapes
+/// (such as `%20`).
+fn check_trailing_whitespaces(
+    original: &[u8],
+    edited: &[u8],
+    sep: u8,
+    format: EditFormat,
+) -> anyhow::Result<()> {
+    let original_lines = lines(original, sep).collect::<Vec<_>>();
+    let mut problems = Vec::new();
+    for (index, line) in lines(edited, sep).enumerate() {
+        let (source, dest) = match format {
+            EditFormat::Lines => (original_lines.get(index).copied(), line),
+            EditFormat::Pairs => match line.iter().position(|&b| b == b'\t') {
+                Some(pos) => (Some(&line[..pos]), &line[(pos + 1)..]),
+                None => continue,
+            },
+        };
+        if source == Some(dest) {
+            continue;
+        }
+        if dest.last().map_or(false, |&b| b == b' ' || b == b'\t') {
+            // Line numbers are 1-based.
+            problems.push(index + 1);
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "new filenames at lines {:?} end with whitespaces. \
+             remove them, or write them in escaped forms to keep them \
+             (such as `foo%20` with `--escape percent`, or `\\foo%20` with `--escape auto`)",
+            problems
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns an iterator of lines without line separators.
+///
+/// The last line can be unterminated.
+fn lines(buffer: &[u8], sep: u8) -> impl Iterator<Item = &[u8]> {
+    let num_lines = if buffer.is_empty() { 0 } else { usize::MAX };
+    buffer
+        .strip_suffix(&[sep])
+        .unwrap_or(buffer)
+        .split(move |&b| b == sep)
+        .take(num_lines)
+}
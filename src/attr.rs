@@ -0,0 +1,53 @@
+//! File attributes on Linux filesystems, as listed by `lsattr`.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Immutable attribute.
+pub(crate) const IMMUTABLE: u8 = b'i';
+
+/// Append-only attribute.
+pub(crate) const APPEND_ONLY: u8 = b'a';
+
+/// Casefold attribute of directories.
+pub(crate) const CASEFOLD: u8 = b'F';
+
+/// Lists the attributes of the given files and directories by `lsattr`.
+///
+/// Directories are listed themselves, not their contents. Paths on
+/// filesystems without the attributes are omitted from the result.
+///
+/// # Failures
+///
+/// Fails if `lsattr` cannot be run.
+pub(crate) fn list<I, P>(paths: I) -> io::Result<HashMap<PathBuf, Vec<u8>>>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<OsStr>,
+{
+    let output = Command::new("lsattr")
+        .arg("-d")
+        .arg("--")
+        .args(paths)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    // `lsattr` fails on filesystems without the attributes, but still
+    // prints the attributes of the other paths.
+    let attrs = output
+        .stdout
+        .split(|&b| b == b'\n')
+        .filter_map(|line| {
+            // Each line has the attributes and the path separated by a space.
+            let pos = line.iter().position(|&b| b == b' ')?;
+            let path = Path::new(OsStr::from_bytes(&line[(pos + 1)..]));
+            Some((path.to_owned(), line[..pos].to_vec()))
+        })
+        .collect();
+
+    Ok(attrs)
+}
@@ -0,0 +1,420 @@
+//! Filesystem backends to run rename plans.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::renamer::TEMPDIR_PREFIX;
+
+/// Placeholder of the temporary directory name on dry-run.
+const DRY_RUN_TEMPDIR_NAME: &str = "{{tempdir}}";
+
+/// Backend of the filesystem operations run by a rename plan.
+///
+/// All paths given to and returned from the backend are relative to the
+/// source directory.
+pub trait Renamer {
+    /// Creates a new temporary directory with a unique name, and returns the name.
+    fn create_temp_dir(&mut self, source_dir: &Path) -> io::Result<PathBuf>;
+
+    /// Renames the file.
+    fn rename(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()>;
+
+    /// Moves the file to another filesystem by copy and delete.
+    ///
+    /// This is used where `rename` fails with `EXDEV`. Unlike `rename`, this
+    /// is not atomic.
+    fn move_across_devices(
+        &mut self,
+        source_dir: &Path,
+        rel_src: &Path,
+        rel_dest: &Path,
+    ) -> io::Result<()>;
+
+    /// Copies the file, or the directory recursively.
+    ///
+    /// An existing file at the destination is overwritten, but an existing
+    /// directory is not.
+    fn copy(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()>;
+
+    /// Creates a symbolic link to the target.
+    ///
+    /// The target is written to the link as is, and is not relative to the
+    /// source directory.
+    fn symlink(&mut self, source_dir: &Path, target: &Path, rel_dest: &Path) -> io::Result<()>;
+
+    /// Creates a hard link to the file.
+    fn hard_link(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()>;
+
+    /// Removes the temporary directory, which should be empty.
+    fn remove_temp_dir(&mut self, source_dir: &Path, rel_path: &Path) -> io::Result<()>;
+}
+
+/// Creates a temporary directory in the source directory, and returns the name.
+fn create_temp_dir_on_disk(source_dir: &Path) -> io::Result<PathBuf> {
+    // Use `tempfile::TempDir::into_path()` in order to avoid user files
+    // to be removed by accident when I/O errors happened on rename.
+    // In other words, all we need here is just creating a temporary
+    // directory with unique name, but not automatically deleting
+    // temporary directory (on rename failure).
+    let path = tempfile::Builder::new()
+        .prefix(TEMPDIR_PREFIX)
+        .tempdir_in(source_dir)?
+        .into_path();
+
+    Ok(PathBuf::from(path.file_name().expect(
+        "should never fail: [consistency] temporary directory has a name",
+    )))
+}
+
+/// Copies the file or the directory recursively, preserving the permissions
+/// and the timestamps, and also the ownership if `preserve_owner` is true.
+///
+/// Regular files are cloned by reflink if the filesystem supports it, and
+/// copied otherwise. Symbolic links are copied as links.
+fn copy_on_disk(src: &Path, dest: &Path, preserve_owner: bool) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    let atime = filetime::FileTime::from_last_access_time(&metadata);
+    let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dest)?;
+        if preserve_owner {
+            copy_owner(&metadata, dest)?;
+        }
+        return filetime::set_symlink_file_times(dest, atime, mtime);
+    }
+
+    if file_type.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_on_disk(&entry.path(), &dest.join(entry.file_name()), preserve_owner)?;
+        }
+    } else if file_type.is_file() {
+        // `reflink` fails if the destination exists.
+        if let Err(e) = reflink::reflink(src, dest) {
+            log::trace!("reflink failed ({}), falling back to copy: {:?}", e, src);
+            fs::copy(src, dest)?;
+        }
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("cannot copy the special file {:?}", src),
+        ));
+    }
+    // Changing the owner clears the setuid and setgid bits, so do it before
+    // setting the permissions.
+    if preserve_owner {
+        copy_owner(&metadata, dest)?;
+    }
+    // Set these after the contents are written, as they may be read-only.
+    fs::set_permissions(dest, metadata.permissions())?;
+    filetime::set_file_times(dest, atime, mtime)
+}
+
+/// Sets the owner and the group of the file (or the link itself) to the ones
+/// of the metadata.
+///
+/// As `mv` does, only the group is set if the owner cannot be changed, and
+/// the ownership is left as is if neither can be changed.
+fn copy_owner(metadata: &fs::Metadata, dest: &Path) -> io::Result<()> {
+    use nix::errno::Errno;
+    use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = Some(Uid::from_raw(metadata.uid()));
+    let gid = Some(Gid::from_raw(metadata.gid()));
+    let chown = |uid, gid| fchownat(None, dest, uid, gid, FchownatFlags::NoFollowSymlink);
+    match chown(uid, gid) {
+        Err(Errno::EPERM) => match chown(None, gid) {
+            Err(Errno::EPERM) => {
+                log::debug!("not permitted to preserve the ownership of {:?}", dest);
+                Ok(())
+            }
+            res => res.map_err(io::Error::from),
+        },
+        res => res.map_err(io::Error::from),
+    }
+}
+
+/// Removes the file, or the directory and its contents.
+fn remove_on_disk(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Flushes the file, or the directory and its contents recursively, to the disk.
+fn sync_on_disk(path: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(path)?.file_type();
+    if file_type.is_symlink() {
+        // Symbolic links cannot be opened, and are synced with the parent.
+        return Ok(());
+    }
+    if file_type.is_dir() {
+        for entry in fs::read_dir(path)? {
+            sync_on_disk(&entry?.path())?;
+        }
+    }
+    fs::File::open(path)?.sync_all()
+}
+
+/// Moves the file or the directory by copy, fsync, and delete, preserving
+/// the ownership as far as permitted.
+///
+/// The source is removed only after the copy is flushed to the disk. If the
+/// copy fails, the partial copy is removed unless the destination existed
+/// before.
+fn move_on_disk(src: &Path, dest: &Path) -> io::Result<()> {
+    let dest_existed = fs::symlink_metadata(dest).is_ok();
+    let copied = copy_on_disk(src, dest, true).and_then(|()| {
+        sync_on_disk(dest)?;
+        match dest.parent() {
+            Some(parent) => fs::File::open(parent)?.sync_all(),
+            None => Ok(()),
+        }
+    });
+    if let Err(e) = copied {
+        if !dest_existed {
+            if let Err(e) = remove_on_disk(dest) {
+                log::debug!("failed to remove the partial copy {:?}: {}", dest, e);
+            }
+        }
+        return Err(e);
+    }
+    remove_on_disk(src)
+}
+
+/// Backend using `std::fs`.
+///
+/// Note that an existing file at the destination is overwritten.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFs;
+
+impl Renamer for StdFs {
+    fn create_temp_dir(&mut self, source_dir: &Path) -> io::Result<PathBuf> {
+        create_temp_dir_on_disk(source_dir)
+    }
+
+    fn rename(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        log::trace!("rename: {:?} => {:?}", rel_src, rel_dest);
+        fs::rename(source_dir.join(rel_src), source_dir.join(rel_dest))
+    }
+
+    fn move_across_devices(
+        &mut self,
+        source_dir: &Path,
+        rel_src: &Path,
+        rel_dest: &Path,
+    ) -> io::Result<()> {
+        log::trace!("move across devices: {:?} => {:?}", rel_src, rel_dest);
+        move_on_disk(&source_dir.join(rel_src), &source_dir.join(rel_dest))
+    }
+
+    fn copy(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        log::trace!("copy: {:?} => {:?}", rel_src, rel_dest);
+        copy_on_disk(&source_dir.join(rel_src), &source_dir.join(rel_dest), false)
+    }
+
+    fn symlink(&mut self, source_dir: &Path, target: &Path, rel_dest: &Path) -> io::Result<()> {
+        log::trace!("symlink: {:?} => {:?}", target, rel_dest);
+        std::os::unix::fs::symlink(target, source_dir.join(rel_dest))
+    }
+
+    fn hard_link(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        log::trace!("hard_link: {:?} => {:?}", rel_src, rel_dest);
+        fs::hard_link(source_dir.join(rel_src), source_dir.join(rel_dest))
+    }
+
+    fn remove_temp_dir(&mut self, source_dir: &Path, rel_path: &Path) -> io::Result<()> {
+        fs::remove_dir(source_dir.join(rel_path))
+    }
+}
+
+/// Backend using `renameat2(2)` on Linux.
+///
+/// Operations other than renames are the same as `StdFs`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Renameat2 {
+    /// Whether to fail instead of overwriting an existing destination
+    /// (`RENAME_NOREPLACE`).
+    pub no_replace: bool,
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+impl Renamer for Renameat2 {
+    fn create_temp_dir(&mut self, source_dir: &Path) -> io::Result<PathBuf> {
+        StdFs.create_temp_dir(source_dir)
+    }
+
+    fn rename(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        use nix::fcntl::{renameat2, RenameFlags};
+
+        log::trace!(
+            "renameat2: {:?} => {:?} (no_replace = {})",
+            rel_src,
+            rel_dest,
+            self.no_replace
+        );
+        let flags = if self.no_replace {
+            RenameFlags::RENAME_NOREPLACE
+        } else {
+            RenameFlags::empty()
+        };
+        renameat2(
+            None,
+            &source_dir.join(rel_src),
+            None,
+            &source_dir.join(rel_dest),
+            flags,
+        )
+        .map_err(io::Error::from)
+    }
+
+    fn move_across_devices(
+        &mut self,
+        source_dir: &Path,
+        rel_src: &Path,
+        rel_dest: &Path,
+    ) -> io::Result<()> {
+        log::trace!(
+            "move across devices: {:?} => {:?} (no_replace = {})",
+            rel_src,
+            rel_dest,
+            self.no_replace
+        );
+        if self.no_replace && fs::symlink_metadata(source_dir.join(rel_dest)).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("destination {:?} already exists", rel_dest),
+            ));
+        }
+        StdFs.move_across_devices(source_dir, rel_src, rel_dest)
+    }
+
+    fn copy(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.copy(source_dir, rel_src, rel_dest)
+    }
+
+    fn symlink(&mut self, source_dir: &Path, target: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.symlink(source_dir, target, rel_dest)
+    }
+
+    fn hard_link(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.hard_link(source_dir, rel_src, rel_dest)
+    }
+
+    fn remove_temp_dir(&mut self, source_dir: &Path, rel_path: &Path) -> io::Result<()> {
+        StdFs.remove_temp_dir(source_dir, rel_path)
+    }
+}
+
+/// Backend that does nothing.
+///
+/// The temporary directory is named `{{tempdir}}`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DryRun;
+
+impl Renamer for DryRun {
+    fn create_temp_dir(&mut self, _source_dir: &Path) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(DRY_RUN_TEMPDIR_NAME))
+    }
+
+    fn rename(&mut self, _source_dir: &Path, _rel_src: &Path, _rel_dest: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_across_devices(
+        &mut self,
+        _source_dir: &Path,
+        _rel_src: &Path,
+        _rel_dest: &Path,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn copy(&mut self, _source_dir: &Path, _rel_src: &Path, _rel_dest: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn symlink(&mut self, _source_dir: &Path, _target: &Path, _rel_dest: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn hard_link(
+        &mut self,
+        _source_dir: &Path,
+        _rel_src: &Path,
+        _rel_dest: &Path,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn remove_temp_dir(&mut self, _source_dir: &Path, _rel_path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nix::sys::stat::Mode;
+    use nix::unistd::mkfifo;
+
+    #[test]
+    fn failed_move_removes_partial_copy() {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let src = dir.path().join("src");
+        fs::create_dir(&src).expect("failed to create a directory");
+        fs::write(src.join("file"), b"file").expect("failed to write a file");
+        // Special files cannot be copied.
+        mkfifo(&src.join("fifo"), Mode::S_IRUSR | Mode::S_IWUSR).expect("failed to create a FIFO");
+
+        let dest = dir.path().join("dest");
+        let e = move_on_disk(&src, &dest).expect_err("moving a FIFO should fail");
+        assert!(e.to_string().contains("special file"), "{}", e);
+        assert!(
+            fs::symlink_metadata(&dest).is_err(),
+            "partial copy should be removed"
+        );
+        assert!(src.join("file").is_file());
+        assert!(fs::symlink_metadata(src.join("fifo")).is_ok());
+    }
+
+    #[test]
+    fn failed_move_keeps_existing_destination() {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let src = dir.path().join("src");
+        fs::create_dir(&src).expect("failed to create a directory");
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).expect("failed to create a directory");
+        fs::write(dest.join("kept"), b"kept").expect("failed to write a file");
+
+        move_on_disk(&src, &dest).expect_err("moving onto a directory should fail");
+        assert!(dest.join("kept").is_file());
+        assert!(src.is_dir());
+    }
+
+    #[test]
+    fn move_preserves_contents_and_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let src = dir.path().join("src");
+        fs::write(&src, b"contents").expect("failed to write a file");
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o640))
+            .expect("failed to set the permissions");
+
+        let dest = dir.path().join("dest");
+        move_on_disk(&src, &dest).expect("failed to move the file");
+        assert!(fs::symlink_metadata(&src).is_err());
+        assert_eq!(fs::read(&dest).expect("failed to read"), b"contents");
+        let metadata = fs::metadata(&dest).expect("failed to get the metadata");
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+    }
+}
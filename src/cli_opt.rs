@@ -1,23 +1,297 @@
 //! CLI options.
 
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io;
+use std::io::{self, Read as _, Write as _};
 #[cfg(unix)]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
 
 use anyhow::{bail, Context as _};
+use burne::{
+    save_plan, Action, ConflictPolicy, DirLock, DryRun, EditFormat, Escape, Git, LineSeparator,
+    NameCheck, OutputFormat, RenamePlan, RenameSetup, Renamer, Reporter, SavedPlan, StdFs,
+    SuffixTemplate,
+};
 use clap::Clap;
 
-use crate::renamer::{Escape, LineSeparator, RenameSetup, Renamer};
+use crate::artefact;
+use crate::confirm::{self, Answer};
+use crate::mapping::{self, MappingFormat};
+use crate::transform::{self, CaseConversion, CaseLocale, Scope, SubstExpr};
+
+/// Editor used when no editor is specified.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Prefix of the temporary file and directory to be edited.
+const TEMP_FILE_PREFIX: &str = "burne-";
+
+/// Maximum length of the temporary filename in bytes.
+const TEMP_FILE_NAME_MAX: usize = 255;
 
 /// Renames child files in a directory using editor.
+///
+/// A directory named `plan` or `apply` should be specified as `./plan` or
+/// `./apply` so as not to be taken as a subcommand.
 #[derive(Debug, Clone, Clap)]
+#[clap(setting = clap::AppSettings::ArgsNegateSubcommands)]
 pub(crate) struct Opt {
+    /// Subcommand.
+    #[clap(subcommand)]
+    command: Option<Subcommand>,
+    /// Options to create a plan.
+    #[clap(flatten)]
+    edit: EditOpt,
+    /// Options to apply a plan.
+    #[clap(flatten)]
+    run: RunOpt,
+    /// Options to lock the directory.
+    #[clap(flatten)]
+    lock: LockOpt,
+}
+
+impl Opt {
+    /// Runs the rename procedure.
+    pub(crate) fn run(&self) -> anyhow::Result<()> {
+        match &self.command {
+            Some(Subcommand::Plan(opt)) => opt.run(),
+            Some(Subcommand::Apply(opt)) => opt.run(),
+            None => {
+                let _lock = self.lock.lock(&self.edit.source_dir)?;
+                let plan = match self.edit.plan(self.run.needs_confirmation())? {
+                    Some(plan) => plan,
+                    None => return Ok(()),
+                };
+                self.run.apply(&plan)
+            }
+        }
+    }
+}
+
+/// Subcommand.
+#[derive(Debug, Clone, Clap)]
+// Options are parsed only once, so their sizes do not matter.
+#[allow(clippy::large_enum_variant)]
+enum Subcommand {
+    /// Creates a rename plan and saves it to a file without renaming anything.
+    Plan(PlanOpt),
+    /// Applies a rename plan saved by `burne plan`.
+    Apply(ApplyOpt),
+}
+
+/// Options for `burne plan`.
+#[derive(Debug, Clone, Clap)]
+struct PlanOpt {
+    /// Plan file to write, or `-` for stdout.
+    #[clap(short, long, default_value = "-")]
+    out: PathBuf,
+    /// Options to create a plan.
+    #[clap(flatten)]
+    edit: EditOpt,
+    /// Options to lock the directory.
+    #[clap(flatten)]
+    lock: LockOpt,
+}
+
+impl PlanOpt {
+    /// Creates a plan and saves it.
+    fn run(&self) -> anyhow::Result<()> {
+        let _lock = self.lock.lock(&self.edit.source_dir)?;
+        let plan = self
+            .edit
+            .plan(false)?
+            .expect("should never fail: [consistency] plan is not aborted without confirmation");
+        if self.out.as_os_str() == "-" {
+            save_plan(&plan, io::stdout().lock())?;
+        } else {
+            let file = fs::File::create(&self.out)
+                .with_context(|| format!("failed to create the plan file {:?}", self.out))?;
+            save_plan(&plan, io::BufWriter::new(file))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Options for `burne apply`.
+#[derive(Debug, Clone, Clap)]
+struct ApplyOpt {
+    /// Plan file saved by `burne plan`, or `-` for stdin.
+    plan_file: PathBuf,
+    /// Options to apply a plan.
+    #[clap(flatten)]
+    run: RunOpt,
+    /// Options to lock the directory.
+    #[clap(flatten)]
+    lock: LockOpt,
+}
+
+impl ApplyOpt {
+    /// Loads the plan and applies it.
+    fn run(&self) -> anyhow::Result<()> {
+        let content = if self.plan_file.as_os_str() == "-" {
+            let mut content = Vec::new();
+            io::stdin().lock().read_to_end(&mut content)?;
+            content
+        } else {
+            fs::read(&self.plan_file)
+                .with_context(|| format!("failed to open the plan file {:?}", self.plan_file))?
+        };
+        let saved = SavedPlan::read(&content[..])?;
+        // Check the snapshot after the directory is locked, so that it does
+        // not change before the plan is applied.
+        let _lock = self.lock.lock(saved.source_dir())?;
+        let plan = saved.into_plan()?;
+        log::trace!("plan = {:#?}", plan);
+
+        if self.run.needs_confirmation() && !plan.is_empty() {
+            match confirm::confirm(&plan, false)? {
+                Answer::Apply => {}
+                Answer::Abort | Answer::Edit => {
+                    eprintln!("aborted, nothing is renamed");
+                    return Ok(());
+                }
+            }
+        }
+        self.run.apply(&plan)
+    }
+}
+
+/// Options to lock the directory.
+#[derive(Debug, Clone, Clap)]
+struct LockOpt {
+    /// Waits for another burne to finish with the directory, instead of failing.
+    ///
+    /// The directory is locked from listing the files to applying the plan,
+    /// so that the plan is not made from the files another burne is renaming.
+    #[clap(long)]
+    wait: bool,
+}
+
+impl LockOpt {
+    /// Locks the directory for the session.
+    fn lock(&self, dir: &Path) -> anyhow::Result<DirLock> {
+        match DirLock::try_acquire(dir) {
+            Err(e @ burne::Error::DirectoryBusy { .. }) if self.wait => {
+                eprintln!("{}, waiting", e);
+                Ok(DirLock::acquire_wait(dir)?)
+            }
+            res => Ok(res?),
+        }
+    }
+}
+
+/// Options to apply a plan.
+#[derive(Debug, Clone, Clap)]
+struct RunOpt {
+    /// Instead of running rename, just prints filenames before and after the rename.
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+    /// Output format of the operations run (or to be run on dry-run).
+    ///
+    /// `text` prints `"source" => "dest"` lines, `json` prints a JSON object,
+    /// `jsonl` prints a JSON object per operation, `sh` prints a POSIX shell
+    /// script that reproduces the operations, and `null` prints nothing.
+    /// Defaults to `text` on dry-run, and `null` otherwise.
+    #[clap(
+        short, long, parse(try_from_str = OutputFormat::try_from_cli_str),
+        possible_values(OutputFormat::cli_possible_values())
+    )]
+    output: Option<OutputFormat>,
+    /// Applies the plan without confirmation.
+    ///
+    /// Without this, burne shows the summary of the plan and asks whether to
+    /// apply it, and refuses to apply if stdin is not a terminal.
+    #[clap(short, long)]
+    yes: bool,
+    /// Simulates the plan in memory and checks that it reaches the requested
+    /// state before renaming anything.
+    ///
+    /// This is always enabled in debug builds.
+    #[clap(long)]
+    verify: bool,
+    /// Moves tracked files as `git mv` does, and keeps the git index in sync.
+    ///
+    /// Untracked and ignored files are just renamed. Fails if the source
+    /// directory is not inside a git work tree.
+    #[clap(long)]
+    git: bool,
+    /// Continues with the other chains when a rename fails.
+    ///
+    /// A failed chain of renames is undone, and the result of each chain is
+    /// printed at the end. Exits with a non-zero status if any chain failed.
+    #[clap(long)]
+    keep_going: bool,
+}
+
+impl RunOpt {
+    /// Returns true if the plan should be confirmed before applied.
+    fn needs_confirmation(&self) -> bool {
+        !self.dry_run && !self.yes
+    }
+
+    /// Applies the plan and reports the operations.
+    fn apply(&self, plan: &RenamePlan) -> anyhow::Result<()> {
+        if self.verify || cfg!(debug_assertions) {
+            plan.verify()?;
+        }
+        if !self.dry_run {
+            plan.preflight()?;
+        }
+        let mut renamer: Box<dyn Renamer> = if self.dry_run {
+            Box::new(DryRun)
+        } else if self.git {
+            Box::new(Git::new(plan.source_dir())?)
+        } else {
+            if let Some(work_tree) = Git::find_work_tree(plan.source_dir()) {
+                log::debug!(
+                    "the source directory is in the git work tree {:?}, but `--git` is not specified",
+                    work_tree
+                );
+            }
+            Box::new(StdFs)
+        };
+        let format = self.output.unwrap_or(if self.dry_run {
+            OutputFormat::Text
+        } else {
+            OutputFormat::Null
+        });
+        let mut reporter = Reporter::new(
+            format,
+            Box::new(io::stdout()),
+            plan.source_dir(),
+            self.dry_run,
+        )?;
+        if !self.keep_going {
+            // Finish the report even if the run failed, so that it describes
+            // what was done.
+            let result = plan.run(&mut *renamer, &mut reporter);
+            reporter.finish()?;
+            return Ok(result?);
+        }
+
+        let outcomes = plan.run_keep_going(&mut *renamer, &mut reporter)?;
+        reporter.finish()?;
+        for outcome in &outcomes {
+            eprintln!("{}", outcome);
+        }
+        let num_failed = outcomes.iter().filter(|outcome| !outcome.is_done()).count();
+        if num_failed != 0 {
+            bail!("{} of {} chains failed", num_failed, outcomes.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// Options to create a plan.
+#[derive(Debug, Clone, Clap)]
+struct EditOpt {
     /// Source directory that contains files to rename.
     #[clap(default_value = ".")]
-    pub(crate) source_dir: PathBuf,
+    source_dir: PathBuf,
     /// Escape method.
     #[clap(
         short, long, parse(try_from_str = Escape::try_from_cli_str),
@@ -25,83 +299,480 @@ pub(crate) struct Opt {
         default_value = "none"
     )]
     escape: Escape,
-    /// Instead of running rename, just prints filenames before and after the rename.
-    #[clap(short = 'n', long)]
-    dry_run: bool,
+    /// Format of the file to be edited.
+    ///
+    /// `lines` has a new filename per line, and lines correspond to the source
+    /// files by their positions.
+    /// `pairs` has a source filename and a new filename separated by a TAB
+    /// per line, and lines can be reordered or removed.
+    #[clap(
+        short, long, parse(try_from_str = EditFormat::try_from_cli_str),
+        possible_values(EditFormat::cli_possible_values()),
+        default_value = "lines"
+    )]
+    format: EditFormat,
     /// *UNIMPLEMENTED*: Makes parent directories for destination paths as needed.
     ///
     /// Not yet implemented.
     #[clap(short, long)]
+    #[allow(dead_code)]
     parents: bool,
+    /// Checks destination filenames by strict rules valid on any common platforms.
+    ///
+    /// By default, destination filenames are checked by the rules of the
+    /// filesystem they are on.
+    #[clap(long)]
+    portable: bool,
+    /// Editor command.
+    ///
+    /// The command is run by `sh`, so it can have arguments (such as `code --wait`).
+    /// If not specified, `$BURNE_EDITOR`, `$VISUAL`, `$EDITOR`, and `vi` are
+    /// used in this order.
+    #[clap(long)]
+    editor: Option<OsString>,
+    /// Suffix of the temporary file to be edited.
+    ///
+    /// Editors can use this to detect the filetype.
+    #[clap(long, default_value = ".burne")]
+    temp_suffix: OsString,
+    /// Substitution expression to apply instead of opening the editor.
+    ///
+    /// Takes `s/PATTERN/REPLACEMENT/FLAGS` form, such as `s/IMG_(\d+)/photo-$1/`.
+    /// The replacement can refer to capture groups by `$1`, `${name}`, or `\1`,
+    /// and any other `$` (such as in `$HOME`) is a literal `$`.
+    /// Available flags are `g` (replace all matches) and `i` (case-insensitive).
+    /// Can be specified multiple times, and applied in order to the unescaped
+    /// filenames.
+    #[clap(
+        long = "expr", number_of_values = 1,
+        parse(try_from_str = SubstExpr::try_from_cli_str)
+    )]
+    exprs: Vec<SubstExpr>,
+    /// Case to convert the filenames to instead of opening the editor.
+    ///
+    /// `title` and `sentence` capitalize every word or only the first word.
+    /// `snake`, `kebab`, and `camel` join the words by `_`, `-`, or nothing,
+    /// keeping the dots. The conversion is applied before the expressions.
+    /// Letters are converted by the Unicode default mappings, which are not
+    /// specific to any language unless `--case-locale` is given.
+    #[clap(
+        long, parse(try_from_str = CaseConversion::try_from_cli_str),
+        possible_values(CaseConversion::cli_possible_values())
+    )]
+    case: Option<CaseConversion>,
+    /// Language-specific rules of `--case`.
+    ///
+    /// `tr` and `az` convert dotted and dotless i by the Turkish and
+    /// Azerbaijani rules (`I` => `ı`, `i` => `İ`). Rules of other languages are
+    /// not supported.
+    #[clap(
+        long, parse(try_from_str = CaseLocale::try_from_cli_str),
+        possible_values(CaseLocale::cli_possible_values()),
+        requires = "case"
+    )]
+    case_locale: Option<CaseLocale>,
+    /// Opens the editor with the filenames converted by `--case` and `--expr`.
+    #[clap(long)]
+    edit: bool,
+    /// Part of filenames the case conversion and the expressions are applied to.
+    #[clap(
+        long, parse(try_from_str = Scope::try_from_cli_str),
+        possible_values(Scope::cli_possible_values()),
+        default_value = "name"
+    )]
+    scope: Scope,
+    /// Filter command to pipe the lines through instead of opening the editor.
+    ///
+    /// The command is run by `sh`, reads the lines from stdin, and writes
+    /// the new lines to stdout, such as `sed -E s/foo/bar/`.
+    /// The lines are separated and escaped as specified by `--null-data`
+    /// and `--escape`.
+    #[clap(long, conflicts_with_all = &["exprs", "case"])]
+    filter: Option<OsString>,
+    /// Mapping file of sources and destinations to use instead of opening the editor.
+    ///
+    /// Each row of the CSV (or TSV) file has a source filename and a new
+    /// filename, such as `old.txt,new.txt`. Files not mentioned are not
+    /// renamed. Filenames are escaped as specified by `--escape`.
+    /// `-` reads the mapping from stdin.
+    #[clap(long, conflicts_with_all = &["exprs", "case", "filter"])]
+    mapping: Option<PathBuf>,
+    /// Field delimiter of the mapping file, such as `;` or `tab`.
+    ///
+    /// Defaults to `tab` for `.tsv` files, and `,` otherwise.
+    #[clap(long, parse(try_from_str = mapping::try_char_from_cli_str))]
+    mapping_delimiter: Option<u8>,
+    /// Quote character of the mapping file.
+    #[clap(long, parse(try_from_str = mapping::try_char_from_cli_str), default_value = "\"")]
+    mapping_quote: u8,
+    /// Disables quoting in the mapping file.
+    #[clap(long, conflicts_with = "mapping-quote")]
+    mapping_no_quoting: bool,
+    /// Skips the first row of the mapping file as a header.
+    #[clap(long)]
+    mapping_header: bool,
+    /// Copies files to the new names instead of renaming them.
+    ///
+    /// Directories are copied recursively, and permissions and timestamps
+    /// are preserved. Files are cloned by reflink where the filesystem
+    /// supports it.
+    #[clap(long)]
+    copy: bool,
+    /// Creates symbolic links to the files at the new names instead of renaming them.
+    ///
+    /// Link targets are relative to the directories of the links, unless
+    /// `--absolute` is given. Existing files are never replaced, unless they
+    /// are backed up by `--on-conflict overwrite --backup`.
+    #[clap(long, conflicts_with = "copy")]
+    symlink: bool,
+    /// Makes the targets of symbolic links absolute paths.
+    #[clap(long, requires = "symlink")]
+    absolute: bool,
+    /// Creates hard links to the files at the new names instead of renaming them.
+    ///
+    /// Directories cannot be hard-linked. Existing files are never replaced,
+    /// unless they are backed up by `--on-conflict overwrite --backup`.
+    #[clap(long, conflicts_with_all = &["copy", "symlink"])]
+    hardlink: bool,
+    /// How to resolve a destination colliding with an existing file or another destination.
+    ///
+    /// `error` renames nothing. `skip` leaves the file as is. `overwrite`
+    /// replaces the existing file, and the last of the lines with the same
+    /// destination wins. `suffix` renames to a free name such as
+    /// `name (1).ext` instead. For `skip` and `suffix`, the first of the
+    /// lines with the same destination wins.
+    #[clap(
+        long, parse(try_from_str = ConflictPolicy::try_from_cli_str),
+        possible_values(ConflictPolicy::cli_possible_values()),
+        default_value = "error"
+    )]
+    on_conflict: ConflictPolicy,
+    /// Renames existing files to `name~` before overwriting them.
+    ///
+    /// Only valid with `--on-conflict overwrite`.
+    #[clap(long, requires = "on-conflict")]
+    backup: bool,
+    /// Template of the names made by `--on-conflict suffix`.
+    ///
+    /// `{stem}`, `{n}`, and `{ext}` are replaced by the filename without the
+    /// extension, the number from 1, and the extension with the leading dot.
+    #[clap(
+        long, requires = "on-conflict",
+        parse(try_from_str = SuffixTemplate::try_from_cli_str)
+    )]
+    suffix_template: Option<SuffixTemplate>,
     /// Separates the lines by NUL characters.
     #[clap(short = 'z', long = "null-data", parse(from_flag = line_separator_from_null_data_flag))]
     line_sep: LineSeparator,
 }
 
-impl Opt {
-    /// Runs the rename procedure.
-    pub(crate) fn run(&self) -> anyhow::Result<()> {
-        let setup = RenameSetup::new(&self.source_dir)?;
-        log::debug!("setup = {:?}", setup);
+impl EditOpt {
+    /// Returns the action selected by the flags.
+    fn action(&self) -> Action {
+        if self.copy {
+            Action::Copy
+        } else if self.symlink {
+            Action::Symlink {
+                absolute: self.absolute,
+            }
+        } else if self.hardlink {
+            Action::Hardlink
+        } else {
+            Action::Rename
+        }
+    }
 
-        let (mut tempfile, temp_path) = tempfile::NamedTempFile::new()
-            .context("failed to create a temporary file")?
-            .into_parts();
-        log::trace!("temporary file path: {}", temp_path.display());
+    /// Returns the conflict policy selected by the options.
+    fn conflict_policy(&self) -> anyhow::Result<ConflictPolicy> {
+        match (&self.on_conflict, self.backup, &self.suffix_template) {
+            (ConflictPolicy::Overwrite { .. }, backup, None) => {
+                Ok(ConflictPolicy::Overwrite { backup })
+            }
+            (ConflictPolicy::Suffix(_), false, Some(template)) => {
+                Ok(ConflictPolicy::Suffix(template.clone()))
+            }
+            (policy, false, None) => Ok(policy.clone()),
+            (_, true, _) => bail!("`--backup` requires `--on-conflict overwrite`"),
+            (_, _, Some(_)) => bail!("`--suffix-template` requires `--on-conflict suffix`"),
+        }
+    }
 
-        setup.write(&mut tempfile, self.escape, self.line_sep)?;
-        tempfile.sync_all()?;
-        drop(tempfile);
+    /// Creates a plan by letting the user edit the filenames.
+    ///
+    /// If `confirm` is true, the plan is confirmed by the user before returned,
+    /// and `None` is returned if the user aborted.
+    fn plan(&self, confirm: bool) -> anyhow::Result<Option<RenamePlan>> {
+        let setup = RenameSetup::new(&self.source_dir)?
+            .with_action(self.action())
+            .with_conflict_policy(self.conflict_policy()?);
+        log::debug!("setup = {:?}", setup);
 
-        {
-            let editor = Self::get_editor()?;
-            let mut command = std::process::Command::new(&editor);
-            command.arg(&temp_path);
-            let status = command.status()?;
-            if !status.success() {
-                bail!(
-                    "the editor exited unsuccessfully: exit_code={:?}",
-                    status.code()
-                );
-            }
+        let name_check = if self.portable {
+            NameCheck::Portable
+        } else {
+            NameCheck::Native
         };
 
-        let mut tempfile = io::BufReader::new(fs::File::open(&temp_path)?);
+        if let Some(mapping) = &self.mapping {
+            let plan = setup.plan_rows(self.read_mapping(mapping)?, name_check)?;
+            log::trace!("plan = {:#?}", plan);
+            if !confirm || plan.is_empty() {
+                return Ok(Some(plan));
+            }
+            // There is no buffer to edit again.
+            return match confirm::confirm(&plan, false)? {
+                Answer::Apply => Ok(Some(plan)),
+                Answer::Abort | Answer::Edit => {
+                    eprintln!("aborted, nothing is renamed");
+                    Ok(None)
+                }
+            };
+        }
 
-        let plan = setup.plan(&mut tempfile, self.escape, self.line_sep)?;
-        log::trace!("plan = {:#?}", plan);
+        // The buffer is written only here, as the mapping does not need it and
+        // some filenames cannot be written without escaping.
+        let mut buffer = Vec::new();
+        setup.write(&mut buffer, self.escape, self.line_sep, self.format)?;
 
-        let renamer = if self.dry_run {
-            Renamer::DryRun
+        let has_transforms = self.case.is_some() || !self.exprs.is_empty();
+        if self.edit && !has_transforms {
+            bail!("`--edit` requires `--case` or `--expr`");
+        }
+        let mut edited = if let Some(filter) = &self.filter {
+            Self::run_filter(filter, &buffer)?
+        } else if self.edit {
+            self.edit(&self.apply_transforms(&buffer)?)?
+        } else if has_transforms {
+            self.apply_transforms(&buffer)?
         } else {
-            Renamer::StdFs
+            self.edit(&buffer)?
         };
-        plan.run(&renamer)?;
+        loop {
+            let normalized = artefact::normalize(&buffer, edited, self.line_sep, self.format)?;
+            let plan = setup.plan(
+                &mut &normalized[..],
+                self.escape,
+                self.line_sep,
+                self.format,
+                name_check,
+            )?;
+            log::trace!("plan = {:#?}", plan);
 
-        Ok(())
+            if !confirm || plan.is_empty() {
+                return Ok(Some(plan));
+            }
+            match confirm::confirm(&plan, true)? {
+                Answer::Apply => return Ok(Some(plan)),
+                Answer::Abort => {
+                    eprintln!("aborted, nothing is renamed");
+                    return Ok(None);
+                }
+                Answer::Edit => edited = self.edit(&normalized)?,
+            }
+        }
+    }
+
+    /// Reads the rows of the mapping file.
+    fn read_mapping(&self, path: &Path) -> anyhow::Result<Vec<(usize, OsString, OsString)>> {
+        let format = MappingFormat {
+            delimiter: self
+                .mapping_delimiter
+                .unwrap_or_else(|| MappingFormat::default_delimiter(path)),
+            quote: if self.mapping_no_quoting {
+                None
+            } else {
+                Some(self.mapping_quote)
+            },
+            has_header: self.mapping_header,
+        };
+        log::debug!("mapping format = {:?}", format);
+
+        if path.as_os_str() == "-" {
+            mapping::read_mapping(io::stdin().lock(), format, self.escape)
+        } else {
+            let file = fs::File::open(path)
+                .with_context(|| format!("failed to open the mapping file {:?}", path))?;
+            mapping::read_mapping(io::BufReader::new(file), format, self.escape)
+        }
     }
 
-    /// Attempt to get editor command from the environment.
-    fn get_editor() -> anyhow::Result<OsString> {
-        // See `$VISUAL` environment variable.
-        if let Some(visual) = env::var_os("VISUAL") {
-            log::trace!(
-                "`$VISUAL` environment variable found (value = {:?})",
-                visual
+    /// Lets the user edit the buffer using the editor, and returns the edited buffer.
+    fn edit(&self, buffer: &[u8]) -> anyhow::Result<Vec<u8>> {
+        // The private directory is removed with the file when dropped.
+        let (_temp_dir, temp_path, mut tempfile) = self.create_temp_file()?;
+        log::trace!("temporary file path: {}", temp_path.display());
+
+        tempfile.write_all(buffer)?;
+        tempfile.sync_all()?;
+        drop(tempfile);
+
+        let editor = self.get_editor();
+        // Let the shell parse the editor command, as git does.
+        // This allows editor commands with arguments, such as `code --wait`.
+        let mut script = editor.clone();
+        script.push(r#" "$@""#);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&script).arg(&editor).arg(&temp_path);
+        let status = command
+            .status()
+            .with_context(|| format!("failed to run the editor {:?}", editor))?;
+        if !status.success() {
+            bail!(
+                "the editor exited unsuccessfully: exit_code={:?}",
+                status.code()
             );
-            return Ok(visual);
         }
-        // See `$EDITOR` environment variable.
-        if let Some(editor) = env::var_os("EDITOR") {
-            log::trace!(
-                "`$EDITOR` environment variable found (value = {:?})",
-                editor
+
+        fs::read(&temp_path).context("failed to read the edited temporary file")
+    }
+
+    /// Pipes the buffer through the filter command, and returns its output.
+    fn run_filter(filter: &OsStr, buffer: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(filter)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run the filter {:?}", filter))?;
+
+        // Write in another thread, so that the filter does not block on a
+        // full stdout pipe while we are blocking on a full stdin pipe.
+        let mut stdin = child
+            .stdin
+            .take()
+            .expect("should never fail: [consistency] stdin is piped");
+        let input = buffer.to_owned();
+        let writer = thread::spawn(move || stdin.write_all(&input));
+
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("failed to read the output of the filter {:?}", filter))?;
+        match writer.join() {
+            Ok(Ok(())) => {}
+            // The filter may exit without reading the whole input, as `head` does.
+            Ok(Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                log::debug!("the filter did not read the whole input: {}", e)
+            }
+            Ok(Err(e)) => return Err(e).context("failed to write to the filter"),
+            Err(_) => bail!("the thread writing to the filter panicked"),
+        }
+        if !output.status.success() {
+            bail!(
+                "the filter exited unsuccessfully: exit_code={:?}",
+                output.status.code()
             );
-            return Ok(editor);
         }
 
-        bail!("failed to get editor");
+        Ok(output.stdout)
+    }
+
+    /// Applies the case conversion and the substitution expressions to the buffer.
+    fn apply_transforms(&self, buffer: &[u8]) -> anyhow::Result<Vec<u8>> {
+        transform::transform_lines(buffer, self.escape, self.line_sep, self.format, |line| {
+            self.scope.apply(line, |part| {
+                let part = match self.case {
+                    Some(case) => case.apply(part, self.case_locale.unwrap_or(CaseLocale::Default)),
+                    None => part.to_owned(),
+                };
+                self.exprs
+                    .iter()
+                    .fold(part, |part, expr| expr.apply(&part).into_owned())
+            })
+        })
+    }
+
+    /// Creates a temporary file to be edited.
+    ///
+    /// The file is named after the source directory, such as
+    /// `burne-dirname.burne`, so that editors can detect the filetype.
+    /// The file is created with `0600` permissions in a private temporary
+    /// directory, and both are removed when the returned `TempDir` is dropped.
+    fn create_temp_file(&self) -> anyhow::Result<(tempfile::TempDir, PathBuf, fs::File)> {
+        let temp_dir = tempfile::Builder::new()
+            .prefix(TEMP_FILE_PREFIX)
+            .tempdir()
+            .context("failed to create a temporary directory")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o700))
+                .context("failed to make the temporary directory private")?;
+        }
+
+        let dir_name = self
+            .source_dir
+            .canonicalize()
+            .ok()
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "root".to_owned());
+        // Keep the whole filename within the usual limit of a component, so
+        // that a long directory name does not fail with ENAMETOOLONG.
+        let max_len = TEMP_FILE_NAME_MAX
+            .saturating_sub(TEMP_FILE_PREFIX.len())
+            .saturating_sub(self.temp_suffix.len());
+        let dir_name = truncate_at_char_boundary(&dir_name, max_len);
+        let mut file_name = OsString::from(format!("{}{}", TEMP_FILE_PREFIX, dir_name));
+        file_name.push(&self.temp_suffix);
+        let temp_path = temp_dir.path().join(file_name);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(&temp_path)
+            .with_context(|| format!("failed to create a temporary file {:?}", temp_path))?;
+
+        Ok((temp_dir, temp_path, file))
+    }
+
+    /// Returns the editor command.
+    ///
+    /// The editor is taken from `--editor` option, `$BURNE_EDITOR`,
+    /// `$VISUAL`, and `$EDITOR` in this order, and `vi` is used if none of
+    /// them is set.
+    fn get_editor(&self) -> OsString {
+        if let Some(editor) = &self.editor {
+            log::trace!("`--editor` option found (value = {:?})", editor);
+            return editor.clone();
+        }
+        for var in &["BURNE_EDITOR", "VISUAL", "EDITOR"] {
+            match env::var_os(var) {
+                Some(editor) if !editor.is_empty() => {
+                    log::trace!(
+                        "`${}` environment variable found (value = {:?})",
+                        var,
+                        editor
+                    );
+                    return editor;
+                }
+                _ => {}
+            }
+        }
+
+        log::trace!("no editor specified. using `vi`");
+        DEFAULT_EDITOR.into()
+    }
+}
+
+/// Returns the longest prefix of the string within `max_len` bytes, cut at
+/// a char boundary.
+fn truncate_at_char_boundary(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
     }
+    let end = (0..=max_len)
+        .rev()
+        .find(|&end| s.is_char_boundary(end))
+        .expect("should never fail: [consistency] 0 is always a char boundary");
+    &s[..end]
 }
 
 /// Creates a `LineSeparator` from a `null-data` flag.
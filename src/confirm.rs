@@ -0,0 +1,186 @@
+//! Interactive confirmation of a rename plan.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, BufRead as _};
+
+use anyhow::bail;
+use burne::{Action, DryRun, OutputFormat, RenamePlan, Reporter};
+
+/// Answer to the confirmation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Answer {
+    /// Apply the plan.
+    Apply,
+    /// Abort without renaming anything.
+    Abort,
+    /// Edit the file again.
+    Edit,
+}
+
+/// ANSI escape sequences for terminal colors.
+#[derive(Debug, Clone, Copy)]
+struct Colors {
+    /// Color for sources.
+    source: &'static str,
+    /// Color for destinations.
+    dest: &'static str,
+    /// Color for warnings.
+    warning: &'static str,
+    /// Bold.
+    bold: &'static str,
+    /// Reset.
+    reset: &'static str,
+}
+
+impl Colors {
+    /// Returns the colors to be used for stderr.
+    ///
+    /// Colors are disabled if stderr is not a terminal or `$NO_COLOR` is set.
+    fn for_stderr() -> Self {
+        if atty::is(atty::Stream::Stderr) && env::var_os("NO_COLOR").is_none() {
+            Self {
+                source: "\x1b[31m",
+                dest: "\x1b[32m",
+                warning: "\x1b[33m",
+                bold: "\x1b[1m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Self {
+                source: "",
+                dest: "",
+                warning: "",
+                bold: "",
+                reset: "",
+            }
+        }
+    }
+}
+
+/// Shows the summary of the plan and asks the user whether to apply it.
+///
+/// `Answer::Edit` is offered only if `allow_edit` is true.
+/// Fails if stdin is not a terminal.
+pub(crate) fn confirm(plan: &RenamePlan, allow_edit: bool) -> anyhow::Result<Answer> {
+    if !atty::is(atty::Stream::Stdin) {
+        bail!("stdin is not a terminal, refusing to apply the plan without confirmation (use `--yes` to apply)");
+    }
+
+    let colors = Colors::for_stderr();
+    print_summary(plan, colors);
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    loop {
+        let choices = if allow_edit {
+            "y/N/e(dit)/d(etails)"
+        } else {
+            "y/N/d(etails)"
+        };
+        eprint!("{}Apply? [{}]{} ", colors.bold, choices, colors.reset);
+        let mut answer = String::new();
+        if stdin.read_line(&mut answer)? == 0 {
+            // EOF.
+            eprintln!();
+            return Ok(Answer::Abort);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Ok(Answer::Apply),
+            "" | "n" | "no" => return Ok(Answer::Abort),
+            "e" | "edit" if allow_edit => return Ok(Answer::Edit),
+            "d" | "details" => {
+                eprintln!("operations to be run:");
+                let mut reporter = Reporter::new(
+                    OutputFormat::Text,
+                    Box::new(io::stderr()),
+                    plan.source_dir(),
+                    true,
+                )?;
+                plan.run(&mut DryRun, &mut reporter)?;
+                reporter.finish()?;
+            }
+            answer => eprintln!("unknown answer {:?}", answer),
+        }
+    }
+}
+
+/// Prints the summary of the plan to stderr.
+fn print_summary(plan: &RenamePlan, colors: Colors) {
+    let renames = plan.renames();
+    let cross_device = plan.cross_device_renames();
+    let is_cross_device = |source: &OsStr, dest: &OsStr| {
+        cross_device
+            .iter()
+            .any(|(cd_source, cd_dest)| cd_source == source && cd_dest == dest)
+    };
+    for (source, dest) in renames
+        .iter()
+        .filter(|(source, dest)| !is_cross_device(source, dest))
+    {
+        eprintln!(
+            "  {}{:?}{} => {}{:?}{}",
+            colors.source, source, colors.reset, colors.dest, dest, colors.reset
+        );
+    }
+    if !cross_device.is_empty() {
+        eprintln!("moves to other filesystems (copy and delete, slow and not atomic):");
+        for (source, dest) in cross_device {
+            eprintln!(
+                "  {}{:?}{} => {}{:?}{}",
+                colors.source, source, colors.reset, colors.dest, dest, colors.reset
+            );
+        }
+    }
+    let new_names = plan.new_names();
+    for (source, dest) in new_names {
+        eprintln!(
+            "  {}{:?}{} => {}{:?}{} ({})",
+            colors.source,
+            source,
+            colors.reset,
+            colors.dest,
+            dest,
+            colors.reset,
+            plan.action().as_str()
+        );
+    }
+
+    let resolutions = plan.conflict_resolutions();
+    if !resolutions.is_empty() {
+        eprintln!("conflicts resolved by the policy:");
+        for resolution in resolutions {
+            eprintln!("  {}", resolution);
+        }
+    }
+
+    // Renames never create directories (parent directories of the
+    // destinations should exist), so only directories copied recursively
+    // are new.
+    let num_new_dirs = if plan.action() == Action::Copy {
+        new_names
+            .iter()
+            .filter(|(source, _)| plan.source_dir().join(source).is_dir())
+            .count()
+    } else {
+        0
+    };
+
+    let warnings = plan.warnings();
+    for warning in &warnings {
+        eprintln!("{}warning: {}{}", colors.warning, warning, colors.reset);
+    }
+
+    eprintln!(
+        "{}{} renames ({} across filesystems), {} copies or links, {} new directories, {} cycles, {} conflicts resolved, {} warnings{}",
+        colors.bold,
+        renames.len(),
+        cross_device.len(),
+        new_names.len(),
+        num_new_dirs,
+        plan.num_cycles(),
+        resolutions.len(),
+        warnings.len(),
+        colors.reset
+    );
+}
@@ -0,0 +1,282 @@
+//! Conflict policies.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Placeholder of the stem in suffix templates.
+const STEM_PLACEHOLDER: &str = "{stem}";
+/// Placeholder of the number in suffix templates.
+const NUMBER_PLACEHOLDER: &str = "{n}";
+/// Placeholder of the extension (with the leading dot) in suffix templates.
+const EXT_PLACEHOLDER: &str = "{ext}";
+
+/// Suffix appended to the names of backups.
+pub(crate) const BACKUP_SUFFIX: &str = "~";
+
+/// How a destination colliding with an existing file or another destination
+/// is resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConflictPolicy {
+    /// Fails without renaming anything.
+    Error,
+    /// Skips the rename.
+    ///
+    /// Among renames to the same destination, the first one is kept.
+    Skip,
+    /// Overwrites the existing file.
+    ///
+    /// Among renames to the same destination, the last one is kept and the
+    /// others are not run.
+    Overwrite {
+        /// Whether to rename the existing file to `name~` before overwriting.
+        backup: bool,
+    },
+    /// Renames to a free name made by the template instead.
+    ///
+    /// Among renames to the same destination, the first one is kept.
+    Suffix(SuffixTemplate),
+}
+
+impl Default for ConflictPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self::Error
+    }
+}
+
+impl ConflictPolicy {
+    /// Creates a conflict policy from the given string.
+    ///
+    /// `overwrite` does not make backups, and `suffix` uses the default
+    /// template.
+    /// This is intended for use with CLI parser.
+    pub fn try_from_cli_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "error" => Ok(Self::Error),
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite { backup: false }),
+            "suffix" => Ok(Self::Suffix(SuffixTemplate::default())),
+            s => Err(Error::UnknownValue {
+                kind: "conflict policy",
+                value: s.to_owned(),
+            }),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `ConflictPolicy` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub fn cli_possible_values() -> &'static [&'static str] {
+        &["error", "skip", "overwrite", "suffix"]
+    }
+
+    /// Returns the CLI string representation of the policy.
+    pub fn to_cli_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Skip => "skip",
+            Self::Overwrite { .. } => "overwrite",
+            Self::Suffix(_) => "suffix",
+        }
+    }
+}
+
+/// Template of the names made by `ConflictPolicy::Suffix`.
+///
+/// `{stem}`, `{n}`, and `{ext}` are replaced by the stem of the filename,
+/// the number from 1, and the extension with the leading dot (or an empty
+/// string) respectively. The default is `{stem} ({n}){ext}`, which makes
+/// names such as `name (1).ext`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixTemplate {
+    /// Template string.
+    template: String,
+}
+
+impl Default for SuffixTemplate {
+    fn default() -> Self {
+        Self {
+            template: format!(
+                "{} ({}){}",
+                STEM_PLACEHOLDER, NUMBER_PLACEHOLDER, EXT_PLACEHOLDER
+            ),
+        }
+    }
+}
+
+impl SuffixTemplate {
+    /// Creates a template.
+    ///
+    /// Fails if the template has no `{n}`, has an unknown placeholder, or
+    /// has a path separator.
+    pub fn new(template: &str) -> Result<Self, Error> {
+        let invalid = |reason| Error::InvalidSuffixTemplate {
+            template: template.to_owned(),
+            reason,
+        };
+        if !template.contains(NUMBER_PLACEHOLDER) {
+            return Err(invalid("`{n}` is missing"));
+        }
+        if template.contains('/') {
+            return Err(invalid("a path separator is not allowed"));
+        }
+        let rest = template
+            .replace(STEM_PLACEHOLDER, "")
+            .replace(NUMBER_PLACEHOLDER, "")
+            .replace(EXT_PLACEHOLDER, "");
+        if rest.contains('{') || rest.contains('}') {
+            return Err(invalid(
+                "unknown placeholder (available: `{stem}`, `{n}`, `{ext}`)",
+            ));
+        }
+
+        Ok(Self {
+            template: template.to_owned(),
+        })
+    }
+
+    /// Creates a template from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    #[inline]
+    pub fn try_from_cli_str(s: &str) -> Result<Self, Error> {
+        Self::new(s)
+    }
+
+    /// Returns the template string.
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Makes the name with the number for the given destination.
+    ///
+    /// Only the last component of the destination is changed.
+    pub(crate) fn apply(&self, dest: &OsStr, n: usize) -> OsString {
+        let dest = Path::new(dest);
+        let name = dest.file_name().unwrap_or_default().as_bytes();
+        let stem = dest.file_stem().unwrap_or_default().as_bytes();
+        let ext = &name[stem.len()..];
+
+        let mut new_name = Vec::new();
+        let mut rest = self.template.as_str();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(STEM_PLACEHOLDER) {
+                new_name.extend_from_slice(stem);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix(NUMBER_PLACEHOLDER) {
+                new_name.extend_from_slice(n.to_string().as_bytes());
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix(EXT_PLACEHOLDER) {
+                new_name.extend_from_slice(ext);
+                rest = after;
+            } else {
+                let c = rest
+                    .chars()
+                    .next()
+                    .expect("should never fail: [consistency] `rest` is nonempty");
+                new_name.extend_from_slice(&rest.as_bytes()[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+
+        match dest.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                parent.join(OsString::from_vec(new_name)).into_os_string()
+            }
+            _ => OsString::from_vec(new_name),
+        }
+    }
+}
+
+/// What a destination collided with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConflictKind {
+    /// An existing file that is not renamed away.
+    Existing,
+    /// The destination of another line.
+    Destination {
+        /// Line number (1-based) of the other line.
+        other_lineno: usize,
+    },
+}
+
+/// How a conflict was resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Resolution {
+    /// The rename is skipped.
+    Skipped,
+    /// The rename is not run, since another line renames to the same
+    /// destination later.
+    Superseded,
+    /// The existing file is overwritten.
+    Overwritten {
+        /// Name of the backup of the existing file, if made.
+        backup: Option<OsString>,
+    },
+    /// The file is renamed to another name instead.
+    Suffixed {
+        /// Destination actually used.
+        new_dest: OsString,
+    },
+}
+
+impl Resolution {
+    /// Returns the CLI string representation of the policy resolving the
+    /// conflict this way.
+    pub fn policy_str(&self) -> &'static str {
+        match self {
+            Self::Skipped => "skip",
+            Self::Superseded | Self::Overwritten { .. } => "overwrite",
+            Self::Suffixed { .. } => "suffix",
+        }
+    }
+}
+
+/// A conflict resolved by the conflict policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictResolution {
+    /// Line number (1-based).
+    pub lineno: usize,
+    /// Source filename.
+    pub source: OsString,
+    /// Requested destination filename.
+    pub dest: OsString,
+    /// What the destination collided with.
+    pub kind: ConflictKind,
+    /// How the conflict was resolved.
+    pub resolution: Resolution,
+}
+
+impl fmt::Display for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {:?} => {:?}: ",
+            self.lineno, self.source, self.dest
+        )?;
+        match &self.resolution {
+            Resolution::Skipped => f.write_str("skipped")?,
+            Resolution::Superseded => f.write_str("not run")?,
+            Resolution::Overwritten { backup: None } => f.write_str("overwrites")?,
+            Resolution::Overwritten {
+                backup: Some(backup),
+            } => write!(f, "overwrites (backed up to {:?})", backup)?,
+            Resolution::Suffixed { new_dest } => write!(f, "renamed to {:?} instead", new_dest)?,
+        }
+        match self.kind {
+            ConflictKind::Existing => f.write_str(", as the file exists")?,
+            ConflictKind::Destination { other_lineno } => {
+                write!(f, ", as line {} has the same destination", other_lineno)?
+            }
+        }
+        write!(f, " ({})", self.resolution.policy_str())
+    }
+}
@@ -0,0 +1,474 @@
+//! Errors.
+
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::lock::LockOwner;
+use crate::name_check::NameProblem;
+
+/// Error of burne operations.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// I/O error.
+    Io(io::Error),
+    /// I/O error on the specific file.
+    FileIo {
+        /// Path of the file.
+        path: PathBuf,
+        /// Underlying error.
+        source: io::Error,
+    },
+    /// Unknown value is given for an option.
+    UnknownValue {
+        /// Kind of the value, such as `escape method`.
+        kind: &'static str,
+        /// Given value.
+        value: String,
+    },
+    /// The template of suffixed names is invalid.
+    InvalidSuffixTemplate {
+        /// Given template.
+        template: String,
+        /// Reason.
+        reason: &'static str,
+    },
+    /// A filename cannot be written safely by the escape method.
+    Unescapable {
+        /// Filename.
+        name: OsString,
+        /// Escape method.
+        escape: &'static str,
+        /// Reason.
+        reason: &'static str,
+    },
+    /// The edited list has fewer lines than the source entries.
+    TooFewLines,
+    /// A line in the `pairs` format has no TAB separator.
+    MissingPairSeparator {
+        /// Line number (1-based).
+        lineno: usize,
+    },
+    /// Sources do not exist or are specified more than once.
+    InvalidSources(Vec<SourceProblem>),
+    /// Destinations are invalid.
+    InvalidDestinations(Vec<DestProblem>),
+    /// Multiple files are renamed to the same destination.
+    Collisions(Vec<Collision>),
+    /// Files are copied onto the sources of other copies.
+    CopyConflicts(Vec<CopyConflict>),
+    /// Hard links to directories are requested.
+    ///
+    /// Each item is a line number (1-based) and a source directory.
+    HardlinkedDirectories(Vec<(usize, OsString)>),
+    /// Rename chains are inconsistent.
+    InvalidChain {
+        /// Names in the chain.
+        chain: Vec<OsString>,
+        /// Reason.
+        reason: &'static str,
+    },
+    /// The plan file cannot be parsed.
+    PlanFileSyntax(serde_json::Error),
+    /// The plan file has an unsupported format version.
+    UnsupportedPlanFileVersion(u32),
+    /// The source directory in the plan file is not absolute.
+    RelativeSourceDir(PathBuf),
+    /// Files in the directory have changed since the plan was created.
+    DirectoryChanged(Vec<SnapshotMismatch>),
+    /// The directory is not inside a git work tree.
+    NotGitWorkTree(PathBuf),
+    /// The directory is locked by another process.
+    DirectoryBusy {
+        /// Directory.
+        dir: PathBuf,
+        /// Process holding the lock, if known.
+        owner: Option<LockOwner>,
+    },
+    /// The preflight check found operations which would fail.
+    PreflightFailed(Vec<PreflightProblem>),
+    /// The simulation of the plan does not reach the requested final state.
+    ///
+    /// This indicates a bug of the planner.
+    InternalPlanError(Vec<VerificationProblem>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(_) => f.write_str("I/O error"),
+            Self::FileIo { path, .. } => write!(f, "failed to access {:?}", path),
+            Self::UnknownValue { kind, value } => write!(f, "unknown {} {:?}", kind, value),
+            Self::InvalidSuffixTemplate { template, reason } => {
+                write!(f, "invalid suffix template {:?}: {}", template, reason)
+            }
+            Self::Unescapable {
+                name,
+                escape,
+                reason,
+            } => write!(
+                f,
+                "the path {:?} cannot be escaped with the escape method `{}`: {}",
+                name, escape, reason
+            ),
+            Self::TooFewLines => f.write_str("too few entries in the destination file list"),
+            Self::MissingPairSeparator { lineno } => {
+                write!(f, "line {}: TAB separator not found", lineno)
+            }
+            Self::InvalidSources(problems) => write_list(f, "invalid sources found:", problems),
+            Self::InvalidDestinations(problems) => {
+                write_list(f, "invalid destination filenames found:", problems)
+            }
+            Self::Collisions(collisions) => write_list(
+                f,
+                "attempt to rename two files to the same name:",
+                collisions,
+            ),
+            Self::CopyConflicts(conflicts) => write_list(
+                f,
+                "attempt to copy files onto the sources of other copies:",
+                conflicts,
+            ),
+            Self::HardlinkedDirectories(dirs) => {
+                f.write_str("hard links cannot be made to directories:")?;
+                for (lineno, source) in dirs {
+                    write!(f, "\n    line {}: {:?}", lineno, source)?;
+                }
+                Ok(())
+            }
+            Self::InvalidChain { chain, reason } => {
+                write!(f, "invalid rename chain {:?}: {}", chain, reason)
+            }
+            Self::PlanFileSyntax(_) => f.write_str("failed to parse the plan file"),
+            Self::UnsupportedPlanFileVersion(version) => {
+                write!(f, "unsupported plan file version {}", version)
+            }
+            Self::RelativeSourceDir(path) => {
+                write!(f, "source directory {:?} is not absolute", path)
+            }
+            Self::DirectoryChanged(mismatches) => write_list(
+                f,
+                "the directory has changed since the plan was created:",
+                mismatches,
+            ),
+            Self::NotGitWorkTree(path) => write!(f, "{:?} is not inside a git work tree", path),
+            Self::DirectoryBusy {
+                dir,
+                owner: Some(owner),
+            } => write!(f, "directory {:?} is busy, locked by {}", dir, owner),
+            Self::DirectoryBusy { dir, owner: None } => {
+                write!(f, "directory {:?} is busy, locked by another process", dir)
+            }
+            Self::PreflightFailed(problems) => write_list(
+                f,
+                "the plan would fail partway, nothing is renamed:",
+                problems,
+            ),
+            Self::InternalPlanError(problems) => write_list(
+                f,
+                "internal plan error: the plan does not reach the requested state:",
+                problems,
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) | Self::FileIo { source: e, .. } => Some(e),
+            Self::PlanFileSyntax(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Writes the header and the items, an item per line.
+fn write_list<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    header: &str,
+    items: &[T],
+) -> fmt::Result {
+    f.write_str(header)?;
+    for item in items {
+        write!(f, "\n    {}", item)?;
+    }
+
+    Ok(())
+}
+
+/// Location of a destination in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Location {
+    /// Line number (1-based) in the edited list or the mapping file.
+    Line(usize),
+    /// Index (1-based) of the rename chain in the plan file.
+    Chain(usize),
+    /// Index (1-based) of the copy or link in the plan file.
+    NewName(usize),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Line(lineno) => write!(f, "line {}", lineno),
+            Self::Chain(index) => write!(f, "chain {}", index),
+            Self::NewName(index) => write!(f, "new name {}", index),
+        }
+    }
+}
+
+/// A problem of a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceProblem {
+    /// Line number (1-based).
+    pub lineno: usize,
+    /// Source filename.
+    pub source: OsString,
+    /// Line number (1-based) where the source is already specified, or
+    /// `None` if the source does not exist.
+    pub prev_lineno: Option<usize>,
+}
+
+impl fmt::Display for SourceProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.prev_lineno {
+            Some(prev_lineno) => write!(
+                f,
+                "line {}: source {:?} is already specified at line {}",
+                self.lineno, self.source, prev_lineno
+            ),
+            None => write!(
+                f,
+                "line {}: source {:?} does not exist",
+                self.lineno, self.source
+            ),
+        }
+    }
+}
+
+/// A problem of a destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestProblem {
+    /// Location of the destination.
+    pub location: Location,
+    /// Destination filename.
+    pub dest: OsString,
+    /// Problem.
+    pub problem: NameProblem,
+}
+
+impl fmt::Display for DestProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}: {}", self.location, self.dest, self.problem)
+    }
+}
+
+/// Two files renamed to the same destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// Line number (1-based) of the rename.
+    pub lineno: usize,
+    /// Source filename.
+    pub source: OsString,
+    /// Destination filename.
+    pub dest: OsString,
+    /// Line number (1-based) of the other rename to the same destination.
+    pub other_lineno: usize,
+    /// Source filename of the other rename.
+    pub other_source: OsString,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {:?} is renamed to {:?}, as {:?} is at line {}",
+            self.lineno, self.source, self.dest, self.other_source, self.other_lineno
+        )
+    }
+}
+
+/// A copy onto the source of another copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyConflict {
+    /// Line number (1-based) of the copy.
+    pub lineno: usize,
+    /// Destination filename.
+    pub dest: OsString,
+    /// Line number (1-based) of the other copy from the destination.
+    pub source_lineno: usize,
+}
+
+impl fmt::Display for CopyConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {:?} is copied to, but it is copied from at line {}",
+            self.lineno, self.dest, self.source_lineno
+        )
+    }
+}
+
+/// A difference between the directory and the snapshot in a plan file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMismatch {
+    /// Filename.
+    pub name: OsString,
+    /// Kind of the difference.
+    pub kind: SnapshotMismatchKind,
+}
+
+impl fmt::Display for SnapshotMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.name, self.kind)
+    }
+}
+
+/// Kind of a difference between the directory and the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotMismatchKind {
+    /// The file is not recorded in the snapshot.
+    NotInSnapshot,
+    /// The file has been removed.
+    Removed,
+    /// The file has been created.
+    Created,
+    /// The file has been replaced by another file.
+    Replaced,
+}
+
+impl fmt::Display for SnapshotMismatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotInSnapshot => "not found in the snapshot",
+            Self::Removed => "no longer exists",
+            Self::Created => "created after the plan",
+            Self::Replaced => "replaced by another file",
+        })
+    }
+}
+
+/// A problem found by the simulation of a plan.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum VerificationProblem {
+    /// An operation failed in the simulation.
+    Failed(io::Error),
+    /// The destination does not have the file from the source.
+    NotRenamed {
+        /// Source filename.
+        source: OsString,
+        /// Destination filename.
+        dest: OsString,
+    },
+    /// The destination does not have a new copy of or a link to the source.
+    NotCreated {
+        /// Source filename.
+        source: OsString,
+        /// Destination filename.
+        dest: OsString,
+    },
+    /// The file disappeared or was overwritten.
+    Lost {
+        /// Filename before the rename.
+        name: PathBuf,
+    },
+}
+
+impl fmt::Display for VerificationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed(e) => write!(f, "operation failed in the simulation: {}", e),
+            Self::NotRenamed { source, dest } => {
+                write!(f, "{:?} does not end up at {:?}", source, dest)
+            }
+            Self::NotCreated { source, dest } => {
+                write!(f, "{:?} is not copied or linked to {:?}", source, dest)
+            }
+            Self::Lost { name } => write!(f, "{:?} disappears or is overwritten", name),
+        }
+    }
+}
+
+/// An operation of a plan which would fail, found by the preflight check.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PreflightProblem {
+    /// Path of the file or directory.
+    pub path: PathBuf,
+    /// What blocks the operation.
+    pub blocker: Blocker,
+}
+
+impl fmt::Display for PreflightProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.path, self.blocker)
+    }
+}
+
+/// What blocks an operation of a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Blocker {
+    /// The file or directory does not exist.
+    Missing,
+    /// The directory is on a read-only filesystem.
+    ReadOnlyFilesystem,
+    /// No permission to write to the directory.
+    NoWritePermission,
+    /// The file is in a directory with the sticky bit, and neither the file
+    /// nor the directory is owned by the user.
+    StickyNotOwner,
+    /// The file or directory has the immutable attribute.
+    Immutable,
+    /// Entries cannot be removed from the directory, or the file cannot be
+    /// renamed, since it has the append-only attribute.
+    AppendOnly,
+    /// No permission to read the file to copy.
+    NotReadable,
+    /// A hard link cannot be made to a file on another filesystem.
+    CrossDeviceHardlink,
+    /// The filename is too long for the filesystem.
+    NameTooLong {
+        /// Maximum length of a filename in bytes.
+        max: usize,
+    },
+    /// The path is too long for the system.
+    PathTooLong {
+        /// Maximum length of a path in bytes.
+        max: usize,
+    },
+}
+
+impl fmt::Display for Blocker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("does not exist"),
+            Self::ReadOnlyFilesystem => f.write_str("read-only filesystem"),
+            Self::NoWritePermission => f.write_str("no write permission"),
+            Self::StickyNotOwner => {
+                f.write_str("not owned by the user in a directory with the sticky bit")
+            }
+            Self::Immutable => f.write_str("immutable attribute is set"),
+            Self::AppendOnly => f.write_str("append-only attribute is set"),
+            Self::NotReadable => f.write_str("no read permission"),
+            Self::CrossDeviceHardlink => {
+                f.write_str("cannot be hard-linked from another filesystem")
+            }
+            Self::NameTooLong { max } => write!(f, "filename longer than {} bytes", max),
+            Self::PathTooLong { max } => write!(f, "path longer than {} bytes", max),
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Backend that keeps the git index in sync.
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::ops::Bound;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::backend::{Renamer, StdFs};
+use crate::error::Error;
+
+/// Backend that moves tracked files as `git mv` does.
+///
+/// Tracked files (and directories with tracked files) are moved by `git mv`,
+/// so that the index follows the renames and staged changes are kept.
+/// Untracked and ignored files are renamed by `std::fs`, as `StdFs` does.
+/// Copies and links are created by `std::fs`, and are left untracked.
+///
+/// Tracked files are listed once by `git ls-files` when the first file is
+/// renamed, and the list follows the renames done by this backend.
+#[derive(Debug, Clone)]
+pub struct Git {
+    /// Top-level directory of the work tree.
+    work_tree: PathBuf,
+    /// Tracked files in the source directory.
+    tracked: Option<TrackedFiles>,
+}
+
+/// Snapshot of the tracked files in a directory.
+#[derive(Debug, Clone)]
+struct TrackedFiles {
+    /// Directory the snapshot is taken in.
+    source_dir: PathBuf,
+    /// Paths of the tracked files, relative to the directory.
+    paths: BTreeSet<PathBuf>,
+}
+
+impl TrackedFiles {
+    /// Returns the tracked paths of the file, or of the files in the directory.
+    fn under<'a>(&'a self, rel_path: &'a Path) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        // Descendants sort right after the directory, as paths are compared
+        // by components.
+        self.paths
+            .range::<Path, _>((Bound::Included(rel_path), Bound::Unbounded))
+            .take_while(move |path| path.starts_with(rel_path))
+    }
+
+    /// Moves the tracked paths as `git mv -f` does.
+    fn rename(&mut self, rel_src: &Path, rel_dest: &Path) {
+        self.remove(rel_dest);
+        let moved = self.under(rel_src).cloned().collect::<Vec<_>>();
+        for path in moved {
+            self.paths.remove(&path);
+            let rest = path
+                .strip_prefix(rel_src)
+                .expect("should never fail: [consistency] the path is under the source");
+            self.paths.insert(if rest.as_os_str().is_empty() {
+                rel_dest.to_owned()
+            } else {
+                rel_dest.join(rest)
+            });
+        }
+    }
+
+    /// Removes the tracked paths as `git rm -r --cached` does.
+    fn remove(&mut self, rel_path: &Path) {
+        let removed = self.under(rel_path).cloned().collect::<Vec<_>>();
+        for path in removed {
+            self.paths.remove(&path);
+        }
+    }
+}
+
+impl Git {
+    /// Creates a backend for the work tree the directory is in.
+    ///
+    /// Fails if the directory is not inside a git work tree.
+    pub fn new(source_dir: &Path) -> Result<Self, Error> {
+        match Self::find_work_tree(source_dir) {
+            Some(work_tree) => {
+                log::debug!("git work tree: {:?}", work_tree);
+                Ok(Self {
+                    work_tree,
+                    tracked: None,
+                })
+            }
+            None => Err(Error::NotGitWorkTree(source_dir.to_owned())),
+        }
+    }
+
+    /// Returns the top-level directory of the work tree the directory is in,
+    /// or `None` if it is not in a work tree (or git is not available).
+    pub fn find_work_tree(dir: &Path) -> Option<PathBuf> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--show-toplevel"])
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let top = stdout.trim_end_matches('\n');
+                if top.is_empty() {
+                    None
+                } else {
+                    Some(PathBuf::from(top))
+                }
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::debug!("failed to run git: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the top-level directory of the work tree.
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    /// Runs git in the source directory, and fails if git fails.
+    fn run_git<I, S>(&self, source_dir: &Path, args: I) -> io::Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new("git");
+        // Filenames are given as is, not as patterns.
+        command
+            .arg("--literal-pathspecs")
+            .args(args)
+            .current_dir(source_dir)
+            .stdin(Stdio::null());
+        log::trace!("running {:?}", command);
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "{:?} failed ({}): {}",
+                    command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                ),
+            ));
+        }
+
+        Ok(output)
+    }
+
+    /// Returns the snapshot of the tracked files in the source directory.
+    fn tracked_files(&mut self, source_dir: &Path) -> io::Result<&mut TrackedFiles> {
+        let is_loaded = self
+            .tracked
+            .as_ref()
+            .map_or(false, |tracked| tracked.source_dir == source_dir);
+        if !is_loaded {
+            let output = self.run_git(source_dir, ["ls-files", "-z"])?;
+            let paths = output
+                .stdout
+                .split(|&b| b == b'\0')
+                .filter(|path| !path.is_empty())
+                .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+                .collect::<BTreeSet<_>>();
+            log::debug!("{} tracked files in {:?}", paths.len(), source_dir);
+            self.tracked = Some(TrackedFiles {
+                source_dir: source_dir.to_owned(),
+                paths,
+            });
+        }
+
+        Ok(self
+            .tracked
+            .as_mut()
+            .expect("should never fail: [consistency] the snapshot is loaded above"))
+    }
+
+    /// Returns true if the file, or any file in the directory, is tracked.
+    fn is_tracked(&mut self, source_dir: &Path, rel_path: &Path) -> io::Result<bool> {
+        Ok(self
+            .tracked_files(source_dir)?
+            .under(rel_path)
+            .next()
+            .is_some())
+    }
+}
+
+impl Renamer for Git {
+    fn create_temp_dir(&mut self, source_dir: &Path) -> io::Result<PathBuf> {
+        StdFs.create_temp_dir(source_dir)
+    }
+
+    fn rename(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        if !self.is_tracked(source_dir, rel_src)? {
+            return StdFs.rename(source_dir, rel_src, rel_dest);
+        }
+
+        log::trace!("git mv: {:?} => {:?}", rel_src, rel_dest);
+        let dest = source_dir.join(rel_dest);
+        if fs::symlink_metadata(&dest).map_or(false, |metadata| metadata.is_dir()) {
+            // `git mv` moves the source into an existing directory, while
+            // `rename(2)` replaces it if it is empty.
+            fs::remove_dir(&dest)?;
+        }
+        self.run_git(
+            source_dir,
+            [
+                OsStr::new("mv"),
+                OsStr::new("-f"),
+                OsStr::new("--"),
+                rel_src.as_os_str(),
+                rel_dest.as_os_str(),
+            ],
+        )?;
+        self.tracked_files(source_dir)?.rename(rel_src, rel_dest);
+
+        Ok(())
+    }
+
+    fn move_across_devices(
+        &mut self,
+        source_dir: &Path,
+        rel_src: &Path,
+        rel_dest: &Path,
+    ) -> io::Result<()> {
+        let tracked = self.is_tracked(source_dir, rel_src)?;
+        StdFs.move_across_devices(source_dir, rel_src, rel_dest)?;
+        if tracked {
+            // Another filesystem is usually outside the work tree, so the
+            // moved files are just removed from the index.
+            self.run_git(
+                source_dir,
+                [
+                    OsStr::new("rm"),
+                    OsStr::new("-r"),
+                    OsStr::new("--cached"),
+                    OsStr::new("--quiet"),
+                    OsStr::new("--"),
+                    rel_src.as_os_str(),
+                ],
+            )?;
+            self.tracked_files(source_dir)?.remove(rel_src);
+        }
+
+        Ok(())
+    }
+
+    fn copy(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.copy(source_dir, rel_src, rel_dest)
+    }
+
+    fn symlink(&mut self, source_dir: &Path, target: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.symlink(source_dir, target, rel_dest)
+    }
+
+    fn hard_link(&mut self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        StdFs.hard_link(source_dir, rel_src, rel_dest)
+    }
+
+    fn remove_temp_dir(&mut self, source_dir: &Path, rel_path: &Path) -> io::Result<()> {
+        StdFs.remove_temp_dir(source_dir, rel_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    use crate::test_util::{plan_renames, run_plan};
+
+    /// Creates a git repository with the given files, and adds the tracked
+    /// ones to the index.
+    fn init_repo(tracked: &[&str], untracked: &[&str]) -> tempfile::TempDir {
+        let repo = tempfile::tempdir().expect("failed to create a temporary directory");
+        git(repo.path(), &["init", "--quiet"]);
+        for path in tracked.iter().chain(untracked) {
+            let path = repo.path().join(path);
+            fs::create_dir_all(path.parent().expect("files are in the repository"))
+                .expect("failed to create a directory");
+            fs::write(
+                &path,
+                path.file_name().expect("files have names").as_bytes(),
+            )
+            .expect("failed to write a file");
+        }
+        if !tracked.is_empty() {
+            let mut args = vec!["add", "--"];
+            args.extend_from_slice(tracked);
+            git(repo.path(), &args);
+        }
+        repo
+    }
+
+    /// Runs git in the directory, and returns the stdout.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .output()
+            .expect("failed to run git");
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("git output should be UTF-8")
+    }
+
+    /// Returns the files in the index.
+    fn index_files(dir: &Path) -> Vec<String> {
+        git(dir, &["ls-files"]).lines().map(str::to_owned).collect()
+    }
+
+    /// Returns the untracked files.
+    fn untracked_files(dir: &Path) -> Vec<String> {
+        git(dir, &["ls-files", "--others", "--exclude-standard"])
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Plans the renames and runs them by the `Git` backend.
+    fn run_renames(dir: &Path, renames: &[(&str, &str)]) {
+        let plan = plan_renames(dir, renames);
+        let (result, _) = run_plan(&plan, &mut Git::new(dir).expect("not a work tree"));
+        result.expect("failed to run the plan");
+    }
+
+    #[test]
+    fn tracked_rename() {
+        let repo = init_repo(&["a"], &[]);
+        run_renames(repo.path(), &[("a", "b")]);
+
+        assert_eq!(index_files(repo.path()), ["b"]);
+        assert_eq!(
+            fs::read(repo.path().join("b")).expect("failed to read a file"),
+            b"a"
+        );
+    }
+
+    #[test]
+    fn tracked_swap_through_temp_dir() {
+        let repo = init_repo(&["a", "b"], &[]);
+        run_renames(repo.path(), &[("a", "b"), ("b", "a")]);
+
+        assert_eq!(index_files(repo.path()), ["a", "b"]);
+        assert_eq!(
+            fs::read(repo.path().join("a")).expect("failed to read a file"),
+            b"b"
+        );
+        assert_eq!(
+            fs::read(repo.path().join("b")).expect("failed to read a file"),
+            b"a"
+        );
+        // The index follows the contents.
+        git(repo.path(), &["diff", "--quiet"]);
+        assert!(untracked_files(repo.path()).is_empty());
+    }
+
+    #[test]
+    fn directory_with_tracked_and_untracked_files() {
+        let repo = init_repo(&["d/tracked"], &["d/untracked"]);
+        run_renames(repo.path(), &[("d", "e")]);
+
+        assert_eq!(index_files(repo.path()), ["e/tracked"]);
+        assert_eq!(untracked_files(repo.path()), ["e/untracked"]);
+    }
+
+    #[test]
+    fn untracked_file_falls_back_to_std_fs() {
+        let repo = init_repo(&["tracked"], &["a"]);
+        run_renames(repo.path(), &[("a", "b")]);
+
+        assert_eq!(index_files(repo.path()), ["tracked"]);
+        assert_eq!(untracked_files(repo.path()), ["b"]);
+    }
+
+    #[test]
+    fn move_across_devices_removes_from_index() {
+        let repo = init_repo(&["a", "tracked"], &[]);
+        fs::create_dir(repo.path().join("other")).expect("failed to create a directory");
+        let mut backend = Git::new(repo.path()).expect("not a work tree");
+        backend
+            .move_across_devices(repo.path(), Path::new("a"), Path::new("other/a"))
+            .expect("failed to move");
+
+        assert_eq!(index_files(repo.path()), ["tracked"]);
+        assert_eq!(untracked_files(repo.path()), ["other/a"]);
+        assert!(!repo.path().join("a").exists());
+    }
+
+    #[test]
+    fn snapshot_follows_renames() {
+        let repo = init_repo(&["d/x", "d/y", "dz"], &[]);
+        let mut backend = Git::new(repo.path()).expect("not a work tree");
+
+        assert!(backend
+            .is_tracked(repo.path(), Path::new("d"))
+            .expect("failed to list tracked files"));
+        assert!(!backend
+            .is_tracked(repo.path(), Path::new("e"))
+            .expect("failed to list tracked files"));
+        backend
+            .rename(repo.path(), Path::new("d"), Path::new("e"))
+            .expect("failed to rename");
+        assert!(!backend
+            .is_tracked(repo.path(), Path::new("d"))
+            .expect("failed to list tracked files"));
+        assert!(backend
+            .is_tracked(repo.path(), Path::new("e/x"))
+            .expect("failed to list tracked files"));
+        assert!(backend
+            .is_tracked(repo.path(), Path::new("dz"))
+            .expect("failed to list tracked files"));
+        assert_eq!(index_files(repo.path()), ["dz", "e/x", "e/y"]);
+    }
+}
@@ -0,0 +1,55 @@
+//! burne: BUlk ReName by Editor.
+//!
+//! This crate provides the planner and the executor of bulk renames used by
+//! the `burne` command.
+//!
+//! A bulk rename starts from a [`RenameSetup`], which lists the files in a
+//! directory. The setup writes the filenames to a buffer to be edited, and
+//! creates a [`RenamePlan`] from the edited buffer (or from source and
+//! destination pairs). The plan resolves rename chains and cycles, and is
+//! run by a [`Renamer`] backend while the operations are reported to a
+//! [`Reporter`]. Backends include the real filesystem ([`StdFs`]), git work
+//! trees ([`Git`]), dry-run ([`DryRun`]), and an in-memory virtual filesystem
+//! ([`MemoryFs`]) that simulates the plan exactly. Plans can be saved to
+//! files by [`save_plan`] and applied later after [`load_plan`].
+#![forbid(unsafe_code)]
+#![warn(rust_2018_idioms)]
+// `clippy::missing_docs_in_private_items` implies `missing_docs`.
+#![warn(clippy::missing_docs_in_private_items)]
+#![warn(clippy::unwrap_used)]
+
+#[cfg(target_os = "linux")]
+mod attr;
+mod backend;
+mod conflict;
+mod error;
+mod git;
+mod lock;
+mod memory_fs;
+mod name_check;
+mod outcome;
+mod plan_file;
+mod preflight;
+mod renamer;
+mod report;
+#[cfg(test)]
+mod test_util;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub use self::backend::Renameat2;
+pub use self::backend::{DryRun, Renamer, StdFs};
+pub use self::conflict::{
+    ConflictKind, ConflictPolicy, ConflictResolution, Resolution, SuffixTemplate,
+};
+pub use self::error::{
+    Blocker, Collision, CopyConflict, DestProblem, Error, Location, PreflightProblem,
+    SnapshotMismatch, SnapshotMismatchKind, SourceProblem,
+};
+pub use self::git::Git;
+pub use self::lock::{DirLock, LockOwner};
+pub use self::memory_fs::{MemoryEntry, MemoryFs};
+pub use self::name_check::{NameCheck, NameProblem};
+pub use self::outcome::{ChainKind, ChainOutcome, ChainStatus};
+pub use self::plan_file::{load_plan, save_plan, SavedPlan};
+pub use self::renamer::{Action, EditFormat, Escape, LineSeparator, RenamePlan, RenameSetup};
+pub use self::report::{Operation, OutputFormat, Reporter};
@@ -0,0 +1,170 @@
+//! Advisory lock of source directories.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+
+use crate::error::Error;
+
+/// Exclusive advisory lock of a directory.
+///
+/// The lock is taken by `flock(2)` on the directory itself, so nothing is
+/// created in the directory, and the lock is released when this is dropped
+/// or the process exits. The process holding the lock is recorded in a file
+/// in the temporary directory of the system, so that other processes can
+/// tell who holds it.
+#[derive(Debug)]
+pub struct DirLock {
+    /// Open directory holding the lock.
+    _dir: File,
+    /// File recording the owner, if written.
+    owner_path: Option<PathBuf>,
+}
+
+impl DirLock {
+    /// Locks the directory, and fails with `Error::DirectoryBusy` if it is
+    /// locked by another process.
+    ///
+    /// Filesystems which do not support locks are not locked.
+    pub fn try_acquire(dir: &Path) -> Result<Self, Error> {
+        Self::acquire_impl(dir, FlockArg::LockExclusiveNonblock)
+    }
+
+    /// Locks the directory, waiting for other processes to release it.
+    ///
+    /// Filesystems which do not support locks are not locked.
+    pub fn acquire_wait(dir: &Path) -> Result<Self, Error> {
+        Self::acquire_impl(dir, FlockArg::LockExclusive)
+    }
+
+    /// Locks the directory.
+    fn acquire_impl(dir: &Path, arg: FlockArg) -> Result<Self, Error> {
+        let file_io = |source| Error::FileIo {
+            path: dir.to_owned(),
+            source,
+        };
+        let file = File::open(dir).map_err(file_io)?;
+        let metadata = file.metadata().map_err(file_io)?;
+        let owner_path =
+            env::temp_dir().join(format!("burne-{}-{}.lock", metadata.dev(), metadata.ino()));
+
+        match flock(file.as_raw_fd(), arg) {
+            Ok(()) => {}
+            Err(Errno::EWOULDBLOCK) => {
+                return Err(Error::DirectoryBusy {
+                    dir: dir.to_owned(),
+                    owner: LockOwner::read(&owner_path),
+                });
+            }
+            Err(e) => {
+                log::debug!("failed to lock {:?}, continuing without lock: {}", dir, e);
+                return Ok(Self {
+                    _dir: file,
+                    owner_path: None,
+                });
+            }
+        }
+        log::debug!("locked {:?}", dir);
+
+        let owner_path = match LockOwner::current().write(&owner_path) {
+            Ok(()) => Some(owner_path),
+            Err(e) => {
+                log::debug!("failed to record the lock owner to {:?}: {}", owner_path, e);
+                None
+            }
+        };
+
+        Ok(Self {
+            _dir: file,
+            owner_path,
+        })
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if let Some(owner_path) = &self.owner_path {
+            if let Err(e) = fs::remove_file(owner_path) {
+                log::debug!("failed to remove {:?}: {}", owner_path, e);
+            }
+        }
+    }
+}
+
+/// Process holding a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockOwner {
+    /// Process ID.
+    pub pid: u32,
+    /// Time the lock was taken.
+    pub since: SystemTime,
+}
+
+impl LockOwner {
+    /// Returns the owner record of the current process.
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            since: SystemTime::now(),
+        }
+    }
+
+    /// Reads the owner record, if it exists and the process is alive.
+    fn read(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        let mut fields = content.split_whitespace();
+        let pid = fields.next()?.parse::<u32>().ok()?;
+        let secs = fields.next()?.parse::<u64>().ok()?;
+        // A record left by a crashed process is stale.
+        if kill(Pid::from_raw(pid as i32), None) == Err(Errno::ESRCH) {
+            log::debug!("stale lock owner record {:?} of PID {}", path, pid);
+            return None;
+        }
+
+        Some(Self {
+            pid,
+            since: UNIX_EPOCH + Duration::from_secs(secs),
+        })
+    }
+
+    /// Writes the owner record, replacing the stale one.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let secs = self
+            .since
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(path)?;
+        writeln!(file, "{} {}", self.pid, secs)
+    }
+}
+
+impl fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PID {} since {}",
+            self.pid,
+            humantime::format_rfc3339_seconds(self.since)
+        )
+    }
+}
@@ -5,8 +5,11 @@
 #![warn(clippy::missing_docs_in_private_items)]
 #![warn(clippy::unwrap_used)]
 
+mod artefact;
 mod cli_opt;
-mod renamer;
+mod confirm;
+mod mapping;
+mod transform;
 
 use clap::Clap;
 
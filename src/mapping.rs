@@ -0,0 +1,98 @@
+//! Rename mappings in CSV or TSV files.
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context as _};
+use burne::Escape;
+
+/// Format of a mapping file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MappingFormat {
+    /// Field delimiter.
+    pub(crate) delimiter: u8,
+    /// Quote character, or `None` if quoting is disabled.
+    pub(crate) quote: Option<u8>,
+    /// Whether the first row is a header.
+    pub(crate) has_header: bool,
+}
+
+impl MappingFormat {
+    /// Returns the default delimiter for the mapping file.
+    ///
+    /// TAB is used for `.tsv` files, and comma is used otherwise.
+    pub(crate) fn default_delimiter(path: &Path) -> u8 {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("tsv") => b'\t',
+            _ => b',',
+        }
+    }
+}
+
+/// Parses a delimiter or a quote character.
+///
+/// A single ASCII character or `tab` is accepted.
+/// This is intended for use with CLI parser.
+pub(crate) fn try_char_from_cli_str(s: &str) -> anyhow::Result<u8> {
+    match s.as_bytes() {
+        b"tab" | b"\\t" => Ok(b'\t'),
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err(anyhow!(
+            "expected a single ASCII character or `tab`, but got {:?}",
+            s
+        )),
+    }
+}
+
+/// Reads the rows of a mapping file.
+///
+/// Each row has a source and a destination, which are unescaped by the
+/// given escape method.
+/// Returns line numbers, sources, and destinations. All malformed rows are
+/// reported at once.
+pub(crate) fn read_mapping<R: Read>(
+    reader: R,
+    format: MappingFormat,
+    escape: Escape,
+) -> anyhow::Result<Vec<(usize, OsString, OsString)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter)
+        .quoting(format.quote.is_some())
+        .quote(format.quote.unwrap_or(b'"'))
+        .has_headers(format.has_header)
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+    for record in reader.byte_records() {
+        let record = record.context("failed to read the mapping file")?;
+        let lineno = record
+            .position()
+            .expect("should never fail: [consistency] records read from files have positions")
+            .line();
+        let lineno = usize::try_from(lineno).unwrap_or(usize::MAX);
+        if record.len() != 2 {
+            problems.push(format!(
+                "line {}: expected 2 fields (source and destination), but got {}",
+                lineno,
+                record.len()
+            ));
+            continue;
+        }
+        let source = escape.unescape(record[0].to_vec());
+        let dest = escape.unescape(record[1].to_vec());
+        rows.push((lineno, source, dest));
+    }
+    if !problems.is_empty() {
+        bail!(
+            "malformed rows found in the mapping file:\n    {}",
+            problems.join("\n    ")
+        );
+    }
+    log::debug!("{} rows read from the mapping file", rows.len());
+
+    Ok(rows)
+}
@@ -0,0 +1,366 @@
+//! In-memory virtual filesystem backend.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::backend::Renamer;
+use crate::renamer::TEMPDIR_PREFIX;
+
+/// An entry of the in-memory filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryEntry {
+    /// Unique identity of the entry, kept across renames.
+    pub id: u64,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+}
+
+/// In-memory virtual filesystem.
+///
+/// This simulates `rename(2)` on the entries: a file can replace an existing
+/// file, a directory can replace an existing empty directory, and a renamed
+/// directory carries its descendants. A copy creates new entries for the
+/// source and its descendants, and can replace an existing file only.
+/// Links never replace existing files: a symbolic link is a new file entry,
+/// and a hard link shares the identity with the source. Entries are keyed by paths relative to
+/// the source directory, and the source directory itself always exists.
+///
+/// A failure can be injected to the n-th operation by `fail_at`.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    /// Entries keyed by relative paths.
+    entries: BTreeMap<PathBuf, MemoryEntry>,
+    /// Identity of the next entry.
+    next_id: u64,
+    /// Number of the operations run.
+    num_ops: usize,
+    /// Index of the operation to fail.
+    fail_at: Option<usize>,
+}
+
+impl MemoryFs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a filesystem with the direct children of the given directory.
+    ///
+    /// Descendants of subdirectories are not loaded, but can be added by
+    /// `insert`. Symbolic links to directories are loaded as directories, so
+    /// that destinations can be put through them.
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let mut memfs = Self::new();
+        let mut children = fs::read_dir(dir)?
+            .map(|entry| {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                let is_dir = file_type.is_dir()
+                    || (file_type.is_symlink()
+                        && fs::metadata(entry.path()).map_or(false, |metadata| metadata.is_dir()));
+                Ok((entry.file_name(), is_dir))
+            })
+            .collect::<io::Result<Vec<(OsString, bool)>>>()?;
+        // Sort to make the identities deterministic.
+        children.sort();
+        for (name, is_dir) in children {
+            memfs.insert(PathBuf::from(name), is_dir);
+        }
+
+        Ok(memfs)
+    }
+
+    /// Inserts an entry and returns its identity.
+    ///
+    /// Missing ancestors are also inserted as directories.
+    /// If the entry already exists, it is kept as is.
+    pub fn insert<P: Into<PathBuf>>(&mut self, path: P, is_dir: bool) -> u64 {
+        let path = path.into();
+        if let Some(parent) = parent_of(&path) {
+            if !self.entries.contains_key(parent) {
+                self.insert(parent.to_owned(), true);
+            }
+        }
+        if let Some(entry) = self.entries.get(&path) {
+            return entry.id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(path, MemoryEntry { id, is_dir });
+
+        id
+    }
+
+    /// Injects a failure to the operation at the given index (0-based).
+    pub fn fail_at(&mut self, index: usize) {
+        self.fail_at = Some(index);
+    }
+
+    /// Returns the number of the operations run.
+    pub fn num_ops(&self) -> usize {
+        self.num_ops
+    }
+
+    /// Returns the entry at the given path.
+    pub fn get(&self, path: &Path) -> Option<MemoryEntry> {
+        self.entries.get(path).copied()
+    }
+
+    /// Returns all the entries.
+    pub fn entries(&self) -> &BTreeMap<PathBuf, MemoryEntry> {
+        &self.entries
+    }
+
+    /// Counts the operation, and fails if a failure is injected to it.
+    fn count_op(&mut self) -> io::Result<()> {
+        let index = self.num_ops;
+        self.num_ops += 1;
+        if self.fail_at == Some(index) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("injected failure at the operation {}", index),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the path is the source directory or an existing directory.
+    fn is_dir(&self, path: Option<&Path>) -> bool {
+        match path {
+            None => true,
+            Some(path) => self.entries.get(path).map_or(false, |entry| entry.is_dir),
+        }
+    }
+
+    /// Fails if a link cannot be created at the path.
+    fn check_link_dest(&self, rel_dest: &Path) -> io::Result<()> {
+        if !self.is_dir(parent_of(rel_dest)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("parent directory of {:?} does not exist", rel_dest),
+            ));
+        }
+        if self.entries.contains_key(rel_dest) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("destination {:?} already exists", rel_dest),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the directory has any children.
+    fn has_children(&self, dir: &Path) -> bool {
+        self.descendants(dir).next().is_some()
+    }
+
+    /// Returns the paths of the descendants of the directory.
+    fn descendants<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        self.entries
+            .range::<Path, _>((std::ops::Bound::Excluded(dir), std::ops::Bound::Unbounded))
+            .map(|(path, _)| path)
+            .take_while(move |path| path.starts_with(dir))
+    }
+}
+
+impl Renamer for MemoryFs {
+    fn create_temp_dir(&mut self, _source_dir: &Path) -> io::Result<PathBuf> {
+        self.count_op()?;
+        let path = (0..)
+            .map(|i| PathBuf::from(format!("{}{}", TEMPDIR_PREFIX, i)))
+            .find(|path| !self.entries.contains_key(path))
+            .expect("should never fail: [consistency] there are unused names");
+        self.insert(path.clone(), true);
+
+        Ok(path)
+    }
+
+    fn rename(&mut self, _source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        self.count_op()?;
+        let src_entry = self.entries.get(rel_src).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("source {:?} does not exist", rel_src),
+            )
+        })?;
+        if !self.is_dir(parent_of(rel_dest)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("parent directory of {:?} does not exist", rel_dest),
+            ));
+        }
+        if rel_src == rel_dest {
+            return Ok(());
+        }
+        if rel_dest.starts_with(rel_src) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot move {:?} into itself ({:?})", rel_src, rel_dest),
+            ));
+        }
+        if let Some(dest_entry) = self.entries.get(rel_dest).copied() {
+            match (src_entry.is_dir, dest_entry.is_dir) {
+                (false, true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("destination {:?} is a directory", rel_dest),
+                    ))
+                }
+                (true, false) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("destination {:?} is not a directory", rel_dest),
+                    ))
+                }
+                (true, true) if self.has_children(rel_dest) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("destination directory {:?} is not empty", rel_dest),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        let moved = std::iter::once(rel_src.to_owned())
+            .chain(self.descendants(rel_src).cloned())
+            .collect::<Vec<_>>();
+        for old in moved {
+            let entry = self
+                .entries
+                .remove(&old)
+                .expect("should never fail: [consistency] the path is taken from the entries");
+            let suffix = old
+                .strip_prefix(rel_src)
+                .expect("should never fail: [consistency] descendants start with the source");
+            let new = if suffix.as_os_str().is_empty() {
+                rel_dest.to_owned()
+            } else {
+                rel_dest.join(suffix)
+            };
+            self.entries.insert(new, entry);
+        }
+
+        Ok(())
+    }
+
+    fn move_across_devices(
+        &mut self,
+        source_dir: &Path,
+        rel_src: &Path,
+        rel_dest: &Path,
+    ) -> io::Result<()> {
+        // There are no devices in memory, and the identity is kept as a move.
+        self.rename(source_dir, rel_src, rel_dest)
+    }
+
+    fn copy(&mut self, _source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        self.count_op()?;
+        let src_entry = self.entries.get(rel_src).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("source {:?} does not exist", rel_src),
+            )
+        })?;
+        if !self.is_dir(parent_of(rel_dest)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("parent directory of {:?} does not exist", rel_dest),
+            ));
+        }
+        if rel_dest.starts_with(rel_src) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot copy {:?} into itself ({:?})", rel_src, rel_dest),
+            ));
+        }
+        if let Some(dest_entry) = self.entries.get(rel_dest).copied() {
+            if src_entry.is_dir || dest_entry.is_dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("destination {:?} already exists", rel_dest),
+                ));
+            }
+            self.entries.remove(rel_dest);
+        }
+
+        let copied = std::iter::once((rel_src.to_owned(), src_entry.is_dir))
+            .chain(
+                self.descendants(rel_src)
+                    .map(|path| (path.clone(), self.entries[path].is_dir)),
+            )
+            .collect::<Vec<_>>();
+        for (old, is_dir) in copied {
+            let suffix = old
+                .strip_prefix(rel_src)
+                .expect("should never fail: [consistency] descendants start with the source");
+            let new = if suffix.as_os_str().is_empty() {
+                rel_dest.to_owned()
+            } else {
+                rel_dest.join(suffix)
+            };
+            self.insert(new, is_dir);
+        }
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, _source_dir: &Path, _target: &Path, rel_dest: &Path) -> io::Result<()> {
+        self.count_op()?;
+        self.check_link_dest(rel_dest)?;
+        self.insert(rel_dest.to_owned(), false);
+
+        Ok(())
+    }
+
+    fn hard_link(&mut self, _source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
+        self.count_op()?;
+        let src_entry = self.entries.get(rel_src).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("source {:?} does not exist", rel_src),
+            )
+        })?;
+        if src_entry.is_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("source {:?} is a directory", rel_src),
+            ));
+        }
+        self.check_link_dest(rel_dest)?;
+        self.entries.insert(rel_dest.to_owned(), src_entry);
+
+        Ok(())
+    }
+
+    fn remove_temp_dir(&mut self, _source_dir: &Path, rel_path: &Path) -> io::Result<()> {
+        self.count_op()?;
+        if !self.is_dir(Some(rel_path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("directory {:?} does not exist", rel_path),
+            ));
+        }
+        if self.has_children(rel_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("directory {:?} is not empty", rel_path),
+            ));
+        }
+        self.entries.remove(rel_path);
+
+        Ok(())
+    }
+}
+
+/// Returns the parent of the relative path, or `None` if the parent is the
+/// source directory.
+fn parent_of(path: &Path) -> Option<&Path> {
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+}
@@ -0,0 +1,447 @@
+//! Destination filename checks.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{DestProblem, Error, Location};
+
+/// Maximum length of a filename component in bytes, used when the
+/// filesystem does not tell it.
+const DEFAULT_NAME_MAX: usize = 255;
+
+/// Maximum length of a filename component in UTF-16 code units on
+/// Windows-compatible filesystems.
+const WINDOWS_NAME_MAX_UTF16: usize = 255;
+
+/// Characters that cannot be used in filenames on Windows-compatible filesystems.
+const WINDOWS_FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names reserved on Windows (case insensitive, with or without extensions).
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Filesystem type magic numbers not provided by `nix`.
+#[cfg(target_os = "linux")]
+mod magic {
+    use nix::sys::statfs::FsType;
+
+    /// exFAT.
+    pub(super) const EXFAT_SUPER_MAGIC: FsType = FsType(0x2011_BAB0);
+    /// NTFS by the legacy `ntfs` driver.
+    pub(super) const NTFS_SB_MAGIC: FsType = FsType(0x5346_544E);
+    /// NTFS by the `ntfs3` driver.
+    pub(super) const NTFS3_SUPER_MAGIC: FsType = FsType(0x7366_746E);
+    /// Any FUSE filesystem, including `ntfs-3g`.
+    pub(super) const FUSE_SUPER_MAGIC: FsType = FsType(0x6573_5546);
+}
+
+/// How destination filenames are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameCheck {
+    /// Use the rules of the filesystem the destination is on.
+    Native,
+    /// Use strict rules that are valid on any common platforms.
+    Portable,
+}
+
+/// Filename rules of a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameRules {
+    /// POSIX filesystems: any bytes but `/` and NUL.
+    Posix {
+        /// Maximum length of a component in bytes.
+        name_max: usize,
+    },
+    /// Windows-compatible filesystems such as FAT, exFAT, and NTFS.
+    Windows,
+    /// Strict cross-platform rules.
+    Portable,
+}
+
+impl NameRules {
+    /// Detects the rules of the filesystem the given directory is on.
+    #[cfg(unix)]
+    fn detect(dir: &Path) -> Self {
+        let name_max = match nix::sys::statvfs::statvfs(dir) {
+            Ok(stat) => usize::try_from(stat.name_max()).unwrap_or(usize::MAX),
+            Err(e) => {
+                log::debug!("failed to get statvfs of {:?}: {}", dir, e);
+                DEFAULT_NAME_MAX
+            }
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            use nix::sys::statfs::{statfs, MSDOS_SUPER_MAGIC};
+
+            match statfs(dir) {
+                Ok(stat) => {
+                    let fs_type = stat.filesystem_type();
+                    log::trace!("filesystem type of {:?}: {:?}", dir, fs_type);
+                    if fs_type == MSDOS_SUPER_MAGIC
+                        || fs_type == magic::EXFAT_SUPER_MAGIC
+                        || fs_type == magic::NTFS_SB_MAGIC
+                        || fs_type == magic::NTFS3_SUPER_MAGIC
+                        || (fs_type == magic::FUSE_SUPER_MAGIC && is_fuseblk(dir))
+                    {
+                        return Self::Windows;
+                    }
+                }
+                Err(e) => log::debug!("failed to get statfs of {:?}: {}", dir, e),
+            }
+        }
+
+        Self::Posix { name_max }
+    }
+
+    /// Checks the filename component.
+    fn check_component(self, name: &OsStr) -> Result<(), NameProblem> {
+        match self {
+            Self::Posix { name_max } => {
+                if name.as_bytes().len() > name_max {
+                    return Err(NameProblem::TooLong {
+                        max: name_max,
+                        unit: "bytes",
+                    });
+                }
+                Ok(())
+            }
+            Self::Windows => check_windows_component(name),
+            Self::Portable => {
+                if name.as_bytes().len() > DEFAULT_NAME_MAX {
+                    return Err(NameProblem::TooLong {
+                        max: DEFAULT_NAME_MAX,
+                        unit: "bytes",
+                    });
+                }
+                check_windows_component(name)?;
+                let name = name
+                    .to_str()
+                    .expect("should never fail: [consistency] already checked as valid UTF-8");
+                let base = name.split('.').next().unwrap_or(name).trim_end();
+                if WINDOWS_RESERVED_NAMES
+                    .iter()
+                    .any(|reserved| reserved.eq_ignore_ascii_case(base))
+                {
+                    return Err(NameProblem::ReservedName);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns true if the directory is on a FUSE filesystem backed by a block
+/// device, such as `ntfs-3g` and `exfat-fuse`.
+///
+/// FUSE filesystems share a single magic number, so the mount is looked up
+/// in `/proc/self/mountinfo` by the device number.
+#[cfg(target_os = "linux")]
+fn is_fuseblk(dir: &Path) -> bool {
+    use nix::sys::stat::{major, minor};
+
+    let dev = match fs::metadata(dir) {
+        Ok(metadata) => metadata.dev(),
+        Err(e) => {
+            log::debug!("failed to get the metadata of {:?}: {}", dir, e);
+            return false;
+        }
+    };
+    let dev = format!("{}:{}", major(dev), minor(dev));
+    let mountinfo = match fs::read_to_string("/proc/self/mountinfo") {
+        Ok(v) => v,
+        Err(e) => {
+            log::debug!("failed to read /proc/self/mountinfo: {}", e);
+            return false;
+        }
+    };
+    // Each line has the device number as the third field, and the
+    // filesystem type (such as `fuseblk` or `fuseblk.ntfs-3g`) after the
+    // `-` separator.
+    mountinfo.lines().rev().any(|line| {
+        let mut fields = line.split(' ');
+        fields.nth(2) == Some(&dev)
+            && fields
+                .skip_while(|&field| field != "-")
+                .nth(1)
+                .map_or(false, |fs_type| {
+                    fs_type.split('.').next() == Some("fuseblk")
+                })
+    })
+}
+
+/// Checks the filename component with the rules of Windows-compatible filesystems.
+fn check_windows_component(name: &OsStr) -> Result<(), NameProblem> {
+    let name = name.to_str().ok_or(NameProblem::InvalidUtf8)?;
+    if let Some(c) = name
+        .chars()
+        .find(|&c| (c.is_ascii_control() && c != '\x7f') || WINDOWS_FORBIDDEN_CHARS.contains(&c))
+    {
+        return Err(NameProblem::ForbiddenChar(c));
+    }
+    if name.ends_with(' ') || name.ends_with('.') {
+        return Err(NameProblem::TrailingDotOrSpace);
+    }
+    if name.encode_utf16().count() > WINDOWS_NAME_MAX_UTF16 {
+        return Err(NameProblem::TooLong {
+            max: WINDOWS_NAME_MAX_UTF16,
+            unit: "UTF-16 code units",
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the case-folded name to compare names on case-insensitive filesystems.
+///
+/// Valid UTF-8 names are folded by Unicode lowercase mapping, and others are
+/// folded in ASCII.
+pub(crate) fn fold_case(name: &OsStr) -> OsString {
+    match name.to_str() {
+        Some(name) => name.to_lowercase().into(),
+        None => OsString::from_vec(name.as_bytes().to_ascii_lowercase()),
+    }
+}
+
+/// Returns true if the filenames in the directory are case-insensitive.
+///
+/// This is detected without writing anything to the directory. An existing
+/// entry with ASCII letters is looked up with the name in another case, so
+/// that both case-insensitive filesystems (such as vfat and exFAT) and
+/// casefolded directories (such as ones on ext4 with `+F` attribute) are
+/// detected. If the directory has no such entry, the filesystem type and the
+/// casefold attribute of the directory are checked instead.
+pub(crate) fn is_case_insensitive(dir: &Path) -> bool {
+    match lookup_in_swapped_case(dir) {
+        Ok(Some(v)) => return v,
+        Ok(None) => {}
+        Err(e) => log::debug!("failed to look up the entries in {:?}: {}", dir, e),
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use nix::sys::statfs::{statfs, MSDOS_SUPER_MAGIC};
+
+        match statfs(dir) {
+            Ok(stat) => {
+                let fs_type = stat.filesystem_type();
+                if fs_type == MSDOS_SUPER_MAGIC || fs_type == magic::EXFAT_SUPER_MAGIC {
+                    return true;
+                }
+            }
+            Err(e) => log::debug!("failed to get statfs of {:?}: {}", dir, e),
+        }
+        match crate::attr::list([dir]) {
+            Ok(attrs) => {
+                return attrs
+                    .values()
+                    .any(|attrs| attrs.contains(&crate::attr::CASEFOLD))
+            }
+            Err(e) => log::debug!("failed to run lsattr: {}", e),
+        }
+    }
+
+    false
+}
+
+/// Looks up the first entry of the directory with ASCII letters by the name
+/// with the case of the letters swapped.
+///
+/// Returns whether the lookup found the entry itself, or `None` if the
+/// directory has no entry to look up.
+fn lookup_in_swapped_case(dir: &Path) -> io::Result<Option<bool>> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !name.as_bytes().iter().any(u8::is_ascii_alphabetic) {
+            continue;
+        }
+        let swapped = name
+            .as_bytes()
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_lowercase() {
+                    b.to_ascii_uppercase()
+                } else {
+                    b.to_ascii_lowercase()
+                }
+            })
+            .collect::<Vec<_>>();
+        let metadata = entry.metadata()?;
+        return match fs::symlink_metadata(dir.join(OsStr::from_bytes(&swapped))) {
+            Ok(swapped) => Ok(Some(
+                metadata.dev() == swapped.dev() && metadata.ino() == swapped.ino(),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Some(false)),
+            Err(e) => Err(e),
+        };
+    }
+
+    Ok(None)
+}
+
+/// A problem of a destination filename.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NameProblem {
+    /// Empty filename.
+    Empty,
+    /// NUL character.
+    Nul,
+    /// Absolute path.
+    Absolute,
+    /// Path ends with a path separator.
+    TrailingSeparator,
+    /// `.` or `..` component, which refers to the directory itself or
+    /// leaves it.
+    DotComponent,
+    /// Parent directory does not exist.
+    MissingParent,
+    /// A component is too long.
+    TooLong {
+        /// Maximum length.
+        max: usize,
+        /// Unit of the length.
+        unit: &'static str,
+    },
+    /// Invalid UTF-8 sequence on a filesystem that requires Unicode filenames.
+    InvalidUtf8,
+    /// A character that cannot be used on the filesystem.
+    ForbiddenChar(char),
+    /// Trailing dot or space, silently stripped by Windows-compatible filesystems.
+    TrailingDotOrSpace,
+    /// Reserved device name on Windows.
+    ReservedName,
+    /// A file already exists, and cannot be replaced by a link.
+    AlreadyExists,
+}
+
+impl fmt::Display for NameProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("empty filename"),
+            Self::Nul => f.write_str("contains a NUL character"),
+            Self::Absolute => f.write_str("absolute path is not allowed"),
+            Self::TrailingSeparator => f.write_str("ends with a path separator `/`"),
+            Self::DotComponent => f.write_str("`.` and `..` components are not allowed"),
+            Self::MissingParent => f.write_str("parent directory does not exist"),
+            Self::TooLong { max, unit } => write!(f, "a component is longer than {} {}", max, unit),
+            Self::InvalidUtf8 => f.write_str("invalid UTF-8 sequence is not allowed"),
+            Self::ForbiddenChar(c) => write!(f, "forbidden character {:?}", c),
+            Self::TrailingDotOrSpace => f.write_str("a component ends with a dot or a space"),
+            Self::ReservedName => f.write_str("reserved device name on Windows"),
+            Self::AlreadyExists => f.write_str("a file already exists"),
+        }
+    }
+}
+
+/// Destination filename checker.
+#[derive(Debug)]
+pub(crate) struct NameChecker<'a> {
+    /// Source directory.
+    source_dir: &'a Path,
+    /// Check mode.
+    check: NameCheck,
+    /// Cache of the detected rules for each directory.
+    rules_cache: HashMap<PathBuf, NameRules>,
+}
+
+impl<'a> NameChecker<'a> {
+    /// Creates a new checker for destinations relative to the given directory.
+    pub(crate) fn new(source_dir: &'a Path, check: NameCheck) -> Self {
+        Self {
+            source_dir,
+            check,
+            rules_cache: HashMap::new(),
+        }
+    }
+
+    /// Checks all the given destinations and reports every problem at once.
+    ///
+    /// Each destination is given with its location.
+    pub(crate) fn check_all<'d, I>(&mut self, dests: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (Location, &'d OsStr)>,
+    {
+        let mut problems = Vec::new();
+        for (location, dest) in dests {
+            if let Err(problem) = self.check(dest) {
+                problems.push(DestProblem {
+                    location,
+                    dest: dest.to_owned(),
+                    problem,
+                });
+            }
+        }
+        if !problems.is_empty() {
+            return Err(Error::InvalidDestinations(problems));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the destination.
+    fn check(&mut self, dest: &OsStr) -> Result<(), NameProblem> {
+        let bytes = dest.as_bytes();
+        if bytes.is_empty() {
+            return Err(NameProblem::Empty);
+        }
+        if bytes.contains(&b'\0') {
+            return Err(NameProblem::Nul);
+        }
+        let dest = Path::new(dest);
+        if dest.is_absolute() {
+            return Err(NameProblem::Absolute);
+        }
+        if bytes.ends_with(b"/") {
+            return Err(NameProblem::TrailingSeparator);
+        }
+        // `Path::components` drops `.` components in the middle, so check
+        // the raw components.
+        if bytes
+            .split(|&b| b == b'/')
+            .any(|component| component == b"." || component == b"..")
+        {
+            return Err(NameProblem::DotComponent);
+        }
+
+        let parent = match dest.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => self.source_dir.join(parent),
+            _ => self.source_dir.to_owned(),
+        };
+        if !parent.is_dir() {
+            return Err(NameProblem::MissingParent);
+        }
+        let rules = self.rules_for(parent);
+        for component in dest.components() {
+            if let Component::Normal(name) = component {
+                rules.check_component(name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the filename rules for the given directory.
+    fn rules_for(&mut self, dir: PathBuf) -> NameRules {
+        let check = self.check;
+        *self
+            .rules_cache
+            .entry(dir)
+            .or_insert_with_key(|dir| match check {
+                NameCheck::Native => NameRules::detect(dir),
+                NameCheck::Portable => NameRules::Portable,
+            })
+    }
+}
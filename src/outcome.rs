@@ -0,0 +1,94 @@
+//! Outcomes of independent chains run with keep-going.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+
+/// Kind of an independent unit of a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChainKind {
+    /// Sequential (acyclic) rename chain.
+    Sequential,
+    /// Cyclic rename chain.
+    Cyclic,
+    /// Copy or link to a new name.
+    NewName,
+}
+
+/// Result of an independent unit of a plan.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ChainStatus {
+    /// All the operations succeeded.
+    Done,
+    /// An operation failed.
+    ///
+    /// The operations done before the failure are undone, unless
+    /// `rollback_error` is set.
+    Failed {
+        /// Error of the failed operation.
+        error: io::Error,
+        /// Error of undoing the done operations, which are left partially
+        /// undone.
+        rollback_error: Option<io::Error>,
+    },
+    /// Not attempted, since a chain it depends on failed.
+    Skipped,
+}
+
+/// Outcome of an independent unit of a plan.
+#[derive(Debug)]
+pub struct ChainOutcome {
+    /// Kind of the unit.
+    pub kind: ChainKind,
+    /// Names from the first source to the last destination.
+    ///
+    /// For cyclic chains, the last name is renamed to the first name.
+    pub names: Vec<OsString>,
+    /// Result.
+    pub status: ChainStatus,
+}
+
+impl ChainOutcome {
+    /// Returns true if all the operations of the unit succeeded.
+    pub fn is_done(&self) -> bool {
+        matches!(self.status, ChainStatus::Done)
+    }
+}
+
+impl fmt::Display for ChainOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.status {
+            ChainStatus::Done => "done: ",
+            ChainStatus::Failed { .. } => "failed: ",
+            ChainStatus::Skipped => "skipped: ",
+        })?;
+        let first = self
+            .names
+            .first()
+            .filter(|_| self.kind == ChainKind::Cyclic);
+        for (i, name) in self.names.iter().chain(first).enumerate() {
+            if i != 0 {
+                f.write_str(" => ")?;
+            }
+            write!(f, "{:?}", name)?;
+        }
+        match &self.status {
+            ChainStatus::Done => Ok(()),
+            ChainStatus::Failed {
+                error,
+                rollback_error: None,
+            } => write!(f, ": {} (rolled back)", error),
+            ChainStatus::Failed {
+                error,
+                rollback_error: Some(rollback_error),
+            } => write!(
+                f,
+                ": {} (rollback failed, left partially renamed: {})",
+                error, rollback_error
+            ),
+            ChainStatus::Skipped => f.write_str(": the backup of the destination failed"),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Rename plans saved to files.
+//!
+//! A plan file is a JSON document with the source directory, the rename
+//! chains, the copies or links, and a snapshot of the identities of the files the plan touches.
+//! The snapshot is used to detect changes in the directory between
+//! `burne plan` and `burne apply`.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Location, SnapshotMismatch, SnapshotMismatchKind};
+use crate::name_check::{NameCheck, NameChecker};
+use crate::renamer::{Action, RenamePlan};
+
+/// Version of the plan file format.
+const PLAN_FILE_VERSION: u32 = 1;
+
+/// Path representation in plan files.
+///
+/// A path is represented as a string if it is valid UTF-8, or as
+/// `{"bytes": [...]}` otherwise, as reports do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonPath {
+    /// Valid UTF-8 path.
+    Str(String),
+    /// Path with invalid UTF-8 sequences.
+    Bytes {
+        /// Raw bytes of the path.
+        bytes: Vec<u8>,
+    },
+}
+
+impl From<&OsStr> for JsonPath {
+    fn from(s: &OsStr) -> Self {
+        match s.to_str() {
+            Some(s) => Self::Str(s.to_owned()),
+            None => Self::Bytes {
+                bytes: s.as_bytes().to_vec(),
+            },
+        }
+    }
+}
+
+impl From<JsonPath> for OsString {
+    fn from(path: JsonPath) -> Self {
+        match path {
+            JsonPath::Str(s) => s.into(),
+            JsonPath::Bytes { bytes } => OsString::from_vec(bytes),
+        }
+    }
+}
+
+/// Identity of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileId {
+    /// Device ID.
+    dev: u64,
+    /// Inode number.
+    ino: u64,
+}
+
+impl FileId {
+    /// Returns the identity of the file, or `None` if it does not exist.
+    ///
+    /// Symbolic links are not followed, since they are renamed themselves.
+    fn of(path: &Path) -> io::Result<Option<Self>> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => Ok(Some(Self {
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Snapshot of a file mentioned in the plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SnapshotEntry {
+    /// Filename relative to the source directory.
+    name: JsonPath,
+    /// Identity of the file, or `None` if the file did not exist.
+    id: Option<FileId>,
+}
+
+/// Serialized rename plan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PlanFile {
+    /// Version of the format.
+    version: u32,
+    /// Absolute path of the source directory.
+    source_dir: JsonPath,
+    /// Sequential (acyclic) rename chains.
+    seq_rename_chains: Vec<Vec<JsonPath>>,
+    /// Cyclic rename chains.
+    cyclic_rename_chains: Vec<Vec<JsonPath>>,
+    /// Names to create instead of the names in the chains, which differ only
+    /// in case on case-insensitive filesystems.
+    #[serde(default)]
+    case_renames: Vec<(JsonPath, JsonPath)>,
+    /// Action to create the new names, such as `copy` and `symlink`.
+    #[serde(default = "default_action")]
+    action: String,
+    /// Copies or links as source and destination pairs.
+    #[serde(default)]
+    new_names: Vec<(JsonPath, JsonPath)>,
+    /// Snapshot of the files mentioned in the chains and the new names.
+    snapshot: Vec<SnapshotEntry>,
+}
+
+/// Saves the plan with the snapshot of the current directory.
+pub fn save_plan<W: Write>(plan: &RenamePlan, mut writer: W) -> Result<(), Error> {
+    let source_dir = plan
+        .source_dir()
+        .canonicalize()
+        .map_err(|source| Error::FileIo {
+            path: plan.source_dir().to_owned(),
+            source,
+        })?;
+
+    let mut snapshot = Vec::new();
+    for name in all_names(plan) {
+        let path = source_dir.join(name);
+        let id = FileId::of(&path).map_err(|source| Error::FileIo { path, source })?;
+        snapshot.push(SnapshotEntry {
+            name: name.into(),
+            id,
+        });
+    }
+
+    let plan_file = PlanFile {
+        version: PLAN_FILE_VERSION,
+        source_dir: source_dir.as_os_str().into(),
+        seq_rename_chains: to_json_chains(plan.seq_rename_chains()),
+        cyclic_rename_chains: to_json_chains(plan.cyclic_rename_chains()),
+        case_renames: plan
+            .case_renames()
+            .iter()
+            .map(|(name, new_name)| (name.as_os_str().into(), new_name.as_os_str().into()))
+            .collect(),
+        action: plan.action().as_str().to_owned(),
+        new_names: plan
+            .new_names()
+            .iter()
+            .map(|(src, dest)| (src.as_os_str().into(), dest.as_os_str().into()))
+            .collect(),
+        snapshot,
+    };
+    log::trace!("plan file = {:#?}", plan_file);
+
+    serde_json::to_writer_pretty(&mut writer, &plan_file).map_err(io::Error::from)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Loads the plan, and checks that the directory still matches the snapshot.
+pub fn load_plan<R: Read>(reader: R) -> Result<RenamePlan, Error> {
+    SavedPlan::read(reader)?.into_plan()
+}
+
+/// Plan read from a file, whose snapshot is not checked yet.
+///
+/// This allows locking the source directory before checking the snapshot.
+#[derive(Debug, Clone)]
+pub struct SavedPlan {
+    /// Absolute path of the source directory.
+    source_dir: PathBuf,
+    /// Content of the plan file.
+    plan_file: PlanFile,
+}
+
+impl SavedPlan {
+    /// Reads the plan file.
+    pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
+        let plan_file: PlanFile = serde_json::from_reader(reader).map_err(Error::PlanFileSyntax)?;
+        log::trace!("plan file = {:#?}", plan_file);
+        if plan_file.version != PLAN_FILE_VERSION {
+            return Err(Error::UnsupportedPlanFileVersion(plan_file.version));
+        }
+        let source_dir = PathBuf::from(OsString::from(plan_file.source_dir.clone()));
+        if !source_dir.is_absolute() {
+            return Err(Error::RelativeSourceDir(source_dir));
+        }
+
+        Ok(Self {
+            source_dir,
+            plan_file,
+        })
+    }
+
+    /// Returns the source directory.
+    pub fn source_dir(&self) -> &Path {
+        &self.source_dir
+    }
+
+    /// Creates the plan, and checks that the directory still matches the snapshot.
+    pub fn into_plan(self) -> Result<RenamePlan, Error> {
+        let Self {
+            source_dir,
+            plan_file,
+        } = self;
+        let action = match Action::from_name(&plan_file.action) {
+            Some(action) => action,
+            None => {
+                return Err(Error::UnknownValue {
+                    kind: "action",
+                    value: plan_file.action,
+                })
+            }
+        };
+        let mut plan = RenamePlan::from_chains(
+            source_dir,
+            from_json_chains(plan_file.seq_rename_chains),
+            from_json_chains(plan_file.cyclic_rename_chains),
+            plan_file
+                .case_renames
+                .into_iter()
+                .map(|(name, new_name)| (name.into(), new_name.into()))
+                .collect(),
+            action,
+            plan_file
+                .new_names
+                .into_iter()
+                .map(|(src, dest)| (src.into(), dest.into()))
+                .collect(),
+        )?;
+
+        // Destinations may have been edited by hand after saved.
+        let dests = plan
+            .seq_rename_chains()
+            .iter()
+            .chain(plan.cyclic_rename_chains())
+            .enumerate()
+            .flat_map(|(index, chain)| {
+                chain[1..]
+                    .iter()
+                    .map(move |dest| (Location::Chain(index + 1), dest.as_os_str()))
+            })
+            .chain(
+                plan.new_names()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (_, dest))| (Location::NewName(index + 1), dest.as_os_str())),
+            );
+        NameChecker::new(plan.source_dir(), NameCheck::Native).check_all(dests)?;
+
+        check_snapshot(&plan, plan_file.snapshot)?;
+        plan.detect_cross_device();
+
+        Ok(plan)
+    }
+}
+
+/// Fails if the files mentioned in the plan differ from the snapshot.
+///
+/// All the differences are reported at once.
+fn check_snapshot(plan: &RenamePlan, snapshot: Vec<SnapshotEntry>) -> Result<(), Error> {
+    let snapshot = snapshot
+        .into_iter()
+        .map(|entry| (OsString::from(entry.name), entry.id))
+        .collect::<HashMap<_, _>>();
+
+    let mut mismatches = Vec::new();
+    for name in all_names(plan) {
+        let expected = match snapshot.get(name) {
+            Some(id) => *id,
+            None => {
+                mismatches.push(SnapshotMismatch {
+                    name: name.to_owned(),
+                    kind: SnapshotMismatchKind::NotInSnapshot,
+                });
+                continue;
+            }
+        };
+        let path = plan.source_dir().join(name);
+        let actual = FileId::of(&path).map_err(|source| Error::FileIo { path, source })?;
+        let kind = match (expected, actual) {
+            (Some(_), None) => SnapshotMismatchKind::Removed,
+            (None, Some(_)) => SnapshotMismatchKind::Created,
+            (Some(expected), Some(actual)) if expected != actual => SnapshotMismatchKind::Replaced,
+            _ => continue,
+        };
+        mismatches.push(SnapshotMismatch {
+            name: name.to_owned(),
+            kind,
+        });
+    }
+    if !mismatches.is_empty() {
+        return Err(Error::DirectoryChanged(mismatches));
+    }
+
+    Ok(())
+}
+
+/// Returns the action of plan files without the `action` field.
+fn default_action() -> String {
+    Action::Rename.as_str().to_owned()
+}
+
+/// Returns an iterator of all the names in the chains and the new names.
+fn all_names(plan: &RenamePlan) -> impl Iterator<Item = &OsStr> {
+    plan.seq_rename_chains()
+        .iter()
+        .chain(plan.cyclic_rename_chains())
+        .flatten()
+        .chain(plan.new_names().iter().flat_map(|(src, dest)| [src, dest]))
+        .map(OsString::as_os_str)
+}
+
+/// Converts the chains into the JSON representation.
+fn to_json_chains(chains: &[Vec<OsString>]) -> Vec<Vec<JsonPath>> {
+    chains
+        .iter()
+        .map(|chain| chain.iter().map(|name| name.as_os_str().into()).collect())
+        .collect()
+}
+
+/// Converts the chains from the JSON representation.
+fn from_json_chains(chains: Vec<Vec<JsonPath>>) -> Vec<Vec<OsString>> {
+    chains
+        .into_iter()
+        .map(|chain| chain.into_iter().map(OsString::from).collect())
+        .collect()
+}
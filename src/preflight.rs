@@ -0,0 +1,275 @@
+//! Preflight checks of rename plans.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{access, geteuid, pathconf, AccessFlags, PathconfVar};
+
+#[cfg(target_os = "linux")]
+use crate::attr;
+use crate::error::{Blocker, Error, PreflightProblem};
+use crate::renamer::{Action, RenamePlan};
+
+/// Mode bit of directories with the sticky bit.
+const STICKY_BIT: u32 = 0o1000;
+
+/// Checks that the operations of the plan would succeed as far as possible
+/// without modifying anything, and reports all the blockers found.
+pub(crate) fn check(plan: &RenamePlan) -> Result<(), Error> {
+    let mut preflight = Preflight::new(plan.source_dir());
+
+    let renames = plan.renames();
+    // Names renamed away, which are not replaced by the new names.
+    let sources = renames
+        .iter()
+        .map(|(source, _)| *source)
+        .collect::<HashSet<_>>();
+    for &(source, dest) in &renames {
+        preflight.remove(source);
+        preflight.add(dest, !sources.contains(dest));
+        // Moving a directory to another directory updates its `..` entry.
+        if Path::new(source).parent() != Path::new(dest).parent() {
+            let source_path = plan.source_dir().join(source);
+            if source_path.is_dir() {
+                preflight.writable_dir(&source_path, false);
+            }
+        }
+    }
+    if plan.num_cycles() != 0 {
+        // The temporary directory is created in the source directory.
+        preflight.writable_dir(plan.source_dir(), false);
+    }
+    for (source, dest) in plan.new_names() {
+        match plan.action() {
+            Action::Copy => preflight.readable(source),
+            Action::Hardlink => preflight.same_device(source, dest),
+            _ => preflight.exists(source),
+        }
+        preflight.add(dest, !sources.contains(dest.as_os_str()));
+    }
+    preflight.check_attributes();
+
+    preflight.finish()
+}
+
+/// State of a preflight check.
+#[derive(Debug)]
+struct Preflight<'a> {
+    /// Source directory.
+    source_dir: &'a Path,
+    /// Effective user ID.
+    euid: u32,
+    /// Directories already checked for write permission.
+    checked_dirs: HashSet<PathBuf>,
+    /// Files and directories to check the attributes of, and whether they
+    /// are removed (for files) or have entries removed (for directories).
+    attr_paths: BTreeMap<PathBuf, bool>,
+    /// Problems found.
+    problems: BTreeSet<PreflightProblem>,
+}
+
+impl<'a> Preflight<'a> {
+    /// Creates a new state.
+    fn new(source_dir: &'a Path) -> Self {
+        Self {
+            source_dir,
+            euid: geteuid().as_raw(),
+            checked_dirs: HashSet::new(),
+            attr_paths: BTreeMap::new(),
+            problems: BTreeSet::new(),
+        }
+    }
+
+    /// Records a blocker.
+    fn block(&mut self, path: PathBuf, blocker: Blocker) {
+        log::debug!("preflight: {:?}: {:?}", path, blocker);
+        self.problems.insert(PreflightProblem { path, blocker });
+    }
+
+    /// Checks that the file can be removed from its directory.
+    fn remove(&mut self, rel_path: &OsStr) {
+        let path = self.source_dir.join(rel_path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(v) => v,
+            Err(_) => return self.block(path, Blocker::Missing),
+        };
+        let dir = parent_dir(&path);
+        self.writable_dir(&dir, true);
+        if let Ok(dir_metadata) = fs::metadata(&dir) {
+            let is_sticky = dir_metadata.mode() & STICKY_BIT != 0;
+            if is_sticky
+                && self.euid != 0
+                && metadata.uid() != self.euid
+                && dir_metadata.uid() != self.euid
+            {
+                self.block(path.clone(), Blocker::StickyNotOwner);
+            }
+        }
+        // Symbolic links have no attributes.
+        if !metadata.file_type().is_symlink() {
+            self.attr_paths.insert(path, true);
+        }
+    }
+
+    /// Checks that the file can be created in its directory, and replace the
+    /// existing file if `may_replace` is true.
+    fn add(&mut self, rel_path: &OsStr, may_replace: bool) {
+        let path = self.source_dir.join(rel_path);
+        let dir = parent_dir(&path);
+        self.writable_dir(&dir, false);
+        self.check_length(&path, &dir);
+        if may_replace && fs::symlink_metadata(&path).is_ok() {
+            self.remove(rel_path);
+        }
+    }
+
+    /// Checks that entries can be created in (and removed from, if `removal`
+    /// is true) the directory.
+    fn writable_dir(&mut self, dir: &Path, removal: bool) {
+        *self.attr_paths.entry(dir.to_owned()).or_insert(false) |= removal;
+        if !self.checked_dirs.insert(dir.to_owned()) {
+            return;
+        }
+        match statvfs(dir) {
+            Ok(stat) if stat.flags().contains(FsFlags::ST_RDONLY) => {
+                return self.block(dir.to_owned(), Blocker::ReadOnlyFilesystem);
+            }
+            Err(Errno::ENOENT) => return self.block(dir.to_owned(), Blocker::Missing),
+            _ => {}
+        }
+        match access(dir, AccessFlags::W_OK | AccessFlags::X_OK) {
+            Ok(()) => {}
+            Err(Errno::EROFS) => self.block(dir.to_owned(), Blocker::ReadOnlyFilesystem),
+            Err(Errno::ENOENT) => self.block(dir.to_owned(), Blocker::Missing),
+            Err(e) => {
+                log::debug!("preflight: access to {:?} failed: {}", dir, e);
+                self.block(dir.to_owned(), Blocker::NoWritePermission);
+            }
+        }
+    }
+
+    /// Checks that the filename and the path are not too long.
+    fn check_length(&mut self, path: &Path, dir: &Path) {
+        if let (Some(name), Ok(Some(max))) =
+            (path.file_name(), pathconf(dir, PathconfVar::NAME_MAX))
+        {
+            let max = usize::try_from(max).unwrap_or(usize::MAX);
+            if name.len() > max {
+                self.block(path.to_owned(), Blocker::NameTooLong { max });
+            }
+        }
+        if let Ok(Some(max)) = pathconf(dir, PathconfVar::PATH_MAX) {
+            // The maximum includes the terminating NUL.
+            let max = usize::try_from(max).unwrap_or(usize::MAX).saturating_sub(1);
+            if path.as_os_str().len() > max {
+                self.block(path.to_owned(), Blocker::PathTooLong { max });
+            }
+        }
+    }
+
+    /// Checks that the file exists.
+    fn exists(&mut self, rel_path: &OsStr) {
+        let path = self.source_dir.join(rel_path);
+        if fs::symlink_metadata(&path).is_err() {
+            self.block(path, Blocker::Missing);
+        }
+    }
+
+    /// Checks that the file (or directory) can be read to copy.
+    fn readable(&mut self, rel_path: &OsStr) {
+        let path = self.source_dir.join(rel_path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(v) => v,
+            Err(_) => return self.block(path, Blocker::Missing),
+        };
+        let mode = if metadata.is_dir() {
+            AccessFlags::R_OK | AccessFlags::X_OK
+        } else {
+            AccessFlags::R_OK
+        };
+        // Symbolic links are copied as links, which needs no permission.
+        if !metadata.file_type().is_symlink() && access(&path, mode).is_err() {
+            self.block(path, Blocker::NotReadable);
+        }
+    }
+
+    /// Checks that the file can be hard-linked to the destination.
+    fn same_device(&mut self, rel_src: &OsStr, rel_dest: &OsStr) {
+        let path = self.source_dir.join(rel_src);
+        let source_dev = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata.dev(),
+            Err(_) => return self.block(path, Blocker::Missing),
+        };
+        let dest_path = self.source_dir.join(rel_dest);
+        if let Ok(dir_metadata) = fs::metadata(parent_dir(&dest_path)) {
+            if dir_metadata.dev() != source_dev {
+                self.block(dest_path, Blocker::CrossDeviceHardlink);
+            }
+        }
+    }
+
+    /// Checks the immutable and append-only attributes of the recorded
+    /// files and directories by `lsattr`.
+    ///
+    /// Filesystems without the attributes are ignored. If `lsattr` cannot be
+    /// run, the attributes are not checked and a warning is logged.
+    #[cfg(target_os = "linux")]
+    fn check_attributes(&mut self) {
+        if self.attr_paths.is_empty() {
+            return;
+        }
+        let attrs = match attr::list(self.attr_paths.keys()) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!(
+                    "preflight: failed to run lsattr, \
+                     immutable and append-only files are not checked: {}",
+                    e
+                );
+                return;
+            }
+        };
+        for (path, attrs) in attrs {
+            let removal = match self.attr_paths.get(&path) {
+                Some(&v) => v,
+                None => continue,
+            };
+            if attrs.contains(&attr::IMMUTABLE) {
+                self.block(path, Blocker::Immutable);
+            } else if removal && attrs.contains(&attr::APPEND_ONLY) {
+                self.block(path, Blocker::AppendOnly);
+            }
+        }
+    }
+
+    /// Checks the immutable and append-only attributes of the recorded
+    /// files and directories.
+    ///
+    /// This is not supported on this platform.
+    #[cfg(not(target_os = "linux"))]
+    fn check_attributes(&mut self) {}
+
+    /// Returns the problems found as an error, if any.
+    fn finish(self) -> Result<(), Error> {
+        if self.problems.is_empty() {
+            log::debug!("preflight check passed");
+            return Ok(());
+        }
+
+        Err(Error::PreflightFailed(self.problems.into_iter().collect()))
+    }
+}
+
+/// Returns the parent directory of the path.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -1,15 +1,31 @@
 //! Renamer.
 
-use std::collections::HashMap;
-use std::ffi::OsString;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{self, BufRead, Write};
 #[cfg(unix)]
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
-use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 use std::str;
 
-use anyhow::{anyhow, bail};
+use crate::backend::Renamer;
+use crate::conflict::{
+    ConflictKind, ConflictPolicy, ConflictResolution, Resolution, BACKUP_SUFFIX,
+};
+use crate::error::{
+    Collision, CopyConflict, DestProblem, Error, Location, SourceProblem, VerificationProblem,
+};
+use crate::memory_fs::MemoryFs;
+use crate::name_check::{fold_case, is_case_insensitive, NameCheck, NameChecker, NameProblem};
+use crate::outcome::{ChainKind, ChainOutcome, ChainStatus};
+use crate::preflight;
+use crate::report::{Operation, OutputFormat, Reporter};
+
+/// Prefix of the temporary directory used to break cyclic renames.
+pub(crate) const TEMPDIR_PREFIX: &str = ".burne_";
 
 /// Characters to be escaped by percent encoding.
 const PERCENT_ENCODE_ESCAPE_SET: &percent_encoding::AsciiSet =
@@ -17,7 +33,7 @@ const PERCENT_ENCODE_ESCAPE_SET: &percent_encoding::AsciiSet =
 
 /// Escape method.
 #[derive(Debug, Clone, Copy)]
-pub(crate) enum Escape {
+pub enum Escape {
     /// No escape.
     ///
     /// Cannot rename to filenames with special characters, and fails if the
@@ -33,6 +49,66 @@ pub(crate) enum Escape {
     /// This encodes ASCII control characters, ASCII newline character, and
     /// any non-ASCII characters.
     PercentEncodingAsciiOnly,
+    /// Escapes only filenames that need it.
+    ///
+    /// Filenames without control characters and invalid UTF-8 sequences are
+    /// written as is. Other filenames are written with a leading backslash
+    /// followed by a percent-encoded form, where control characters, invalid
+    /// UTF-8 sequences, and `%` are encoded.
+    Auto,
+}
+
+/// Marker of escaped lines for `Escape::Auto`.
+const AUTO_ESCAPE_MARKER: &str = "\\";
+
+/// Writes the bytes with percent encoding.
+///
+/// Invalid UTF-8 sequences and the characters `should_escape` returns true
+/// for are encoded.
+fn write_percent_encoded<W, F>(mut writer: W, mut bytes: &[u8], should_escape: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(char) -> bool,
+{
+    while !bytes.is_empty() {
+        let (utf8_prefix, non_utf8_suffix, rest) = match str::from_utf8(bytes) {
+            Ok(s) => (s, &b""[..], &bytes[bytes.len()..]),
+            Err(e) => {
+                let valid_up_to = e.valid_up_to();
+                let valid_prefix = str::from_utf8(&bytes[..valid_up_to]).expect(
+                    "should never fail: [consistency] \
+                    already validated by `std::str::from_utf8`",
+                );
+                let rest_index = match e.error_len() {
+                    None => bytes.len(),
+                    Some(len) => valid_up_to + len,
+                };
+                (
+                    valid_prefix,
+                    &bytes[valid_up_to..rest_index],
+                    &bytes[rest_index..],
+                )
+            }
+        };
+        // Escape valid UTF-8 sequence.
+        for c in utf8_prefix.chars() {
+            if should_escape(c) {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    write!(writer, "%{:02X}", byte)?;
+                }
+            } else {
+                write!(writer, "{}", c)?;
+            }
+        }
+        // Escape invalid UTF-8 sequence.
+        for byte in non_utf8_suffix {
+            write!(writer, "%{:02X}", byte)?;
+        }
+        // Process the rest.
+        bytes = rest;
+    }
+
+    Ok(())
 }
 
 impl Escape {
@@ -43,69 +119,49 @@ impl Escape {
     /// Fails if the given path contains a sequence that cannot be escaped
     /// safely by this escape method.
     #[cfg(unix)]
-    fn escape<W: Write>(
+    pub fn escape<W: Write>(
         self,
         mut writer: W,
         path: &Path,
         line_sep: LineSeparator,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), Error> {
         match self {
             Self::None => match path.to_str() {
                 Some(s) => {
                     if s.contains(line_sep.to_char()) {
-                        return Err(anyhow!(
-                            "the path {:?} cannot be escaped with the escape method `none`: \
-                             line separator {:?} found",
-                            s,
-                            line_sep
-                        ));
+                        return Err(Error::Unescapable {
+                            name: s.into(),
+                            escape: "none",
+                            reason: "line separator found",
+                        });
                     }
                     write!(writer, "{}", s)?;
 
                     Ok(())
                 }
-                None => Err(anyhow!(
-                    "the path {:?} cannot be escaped with the escape method `none`: \
-                     invalid UTF-8 sequence",
-                    path
-                )),
+                None => Err(Error::Unescapable {
+                    name: path.into(),
+                    escape: "none",
+                    reason: "invalid UTF-8 sequence",
+                }),
             },
             Self::PercentEncoding => {
-                let mut bytes = path.as_os_str().as_bytes();
-                while !bytes.is_empty() {
-                    let (utf8_prefix, non_utf8_suffix, rest) = match str::from_utf8(bytes) {
-                        Ok(s) => (s, &b""[..], &bytes[bytes.len()..]),
-                        Err(e) => {
-                            let valid_up_to = e.valid_up_to();
-                            let valid_prefix = str::from_utf8(&bytes[..valid_up_to]).expect(
-                                "should never fail: [consistency] \
-                                already validated by `std::str::from_utf8`",
-                            );
-                            let rest_index = match e.error_len() {
-                                None => bytes.len(),
-                                Some(len) => valid_up_to + len,
-                            };
-                            (
-                                valid_prefix,
-                                &bytes[valid_up_to..rest_index],
-                                &bytes[rest_index..],
-                            )
-                        }
-                    };
-                    // Escape valid UTF-8 sequence.
-                    for c in utf8_prefix.chars() {
-                        if c.is_ascii_control() {
-                            write!(writer, "%{:02X}", c as u8)?;
-                        } else {
-                            write!(writer, "{}", c)?;
-                        }
-                    }
-                    // Escape invalid UTF-8 sequence.
-                    for byte in non_utf8_suffix {
-                        write!(writer, "%{:02X}", byte)?;
-                    }
-                    // Process the rest.
-                    bytes = rest;
+                write_percent_encoded(writer, path.as_os_str().as_bytes(), |c| {
+                    c.is_ascii_control()
+                })?;
+
+                Ok(())
+            }
+            Self::Auto => {
+                let bytes = path.as_os_str().as_bytes();
+                let is_plain = str::from_utf8(bytes).map_or(false, |s| {
+                    !s.starts_with(AUTO_ESCAPE_MARKER) && !s.chars().any(char::is_control)
+                });
+                if is_plain {
+                    writer.write_all(bytes)?;
+                } else {
+                    write!(writer, "{}", AUTO_ESCAPE_MARKER)?;
+                    write_percent_encoded(writer, bytes, |c| c.is_control() || c == '%')?;
                 }
 
                 Ok(())
@@ -158,23 +214,25 @@ impl Escape {
         self,
         line_sep: LineSeparator,
         reader: &mut R,
-    ) -> anyhow::Result<Option<OsString>> {
-        // Use `BufRead::has_data_left` once it is stabilized.
-        // See <https://github.com/rust-lang/rust/issues/86423>.
-        if reader.fill_buf()?.is_empty() {
-            return Ok(None);
-        }
+    ) -> io::Result<Option<OsString>> {
+        Ok(line_sep
+            .read_line(reader)?
+            .map(|bytes| self.unescape(bytes)))
+    }
 
-        let mut bytes = Vec::new();
-        reader.read_until(line_sep.to_byte(), &mut bytes)?;
-        if bytes.last() == Some(&line_sep.to_byte()) {
-            bytes.pop();
-        }
+    /// Unescapes the path by the escape method.
+    pub fn unescape(self, bytes: Vec<u8>) -> OsString {
         match self {
-            Self::None => Ok(Some(OsString::from_vec(bytes))),
-            Self::PercentEncoding | Self::PercentEncodingAsciiOnly => Ok(Some(OsString::from_vec(
-                percent_encoding::percent_decode(&bytes).collect(),
-            ))),
+            Self::None => OsString::from_vec(bytes),
+            Self::Auto => match bytes.strip_prefix(AUTO_ESCAPE_MARKER.as_bytes()) {
+                Some(escaped) => {
+                    OsString::from_vec(percent_encoding::percent_decode(escaped).collect())
+                }
+                None => OsString::from_vec(bytes),
+            },
+            Self::PercentEncoding | Self::PercentEncodingAsciiOnly => {
+                OsString::from_vec(percent_encoding::percent_decode(&bytes).collect())
+            }
         }
     }
 }
@@ -183,26 +241,40 @@ impl Escape {
     /// Creates an escape method value from the given string.
     ///
     /// This is intended for use with CLI parser.
-    pub(crate) fn try_from_cli_str(s: &str) -> anyhow::Result<Self> {
+    pub fn try_from_cli_str(s: &str) -> Result<Self, Error> {
         match s {
             "none" => Ok(Self::None),
             "percent" => Ok(Self::PercentEncoding),
             "percent-ascii" => Ok(Self::PercentEncodingAsciiOnly),
-            s => Err(anyhow!("unknown escape method {:?}", s)),
+            "auto" => Ok(Self::Auto),
+            s => Err(Error::UnknownValue {
+                kind: "escape method",
+                value: s.to_owned(),
+            }),
+        }
+    }
+
+    /// Returns the CLI string representation of the escape method.
+    pub fn to_cli_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::PercentEncoding => "percent",
+            Self::PercentEncodingAsciiOnly => "percent-ascii",
+            Self::Auto => "auto",
         }
     }
 
     /// Returns the possible CLI string representation of the `Escape` variants.
     ///
     /// This is intended for use with CLI parser.
-    pub(crate) fn cli_possible_values() -> &'static [&'static str] {
-        &["none", "percent", "percent-ascii"]
+    pub fn cli_possible_values() -> &'static [&'static str] {
+        &["none", "percent", "percent-ascii", "auto"]
     }
 }
 
 /// Line separator character.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum LineSeparator {
+pub enum LineSeparator {
     /// Line feed character (`\n`).
     LineFeed,
     /// Null character (`\0`).
@@ -221,53 +293,254 @@ impl LineSeparator {
 
     /// Returns the line separator character as an ASCII byte.
     #[inline]
-    fn to_byte(self) -> u8 {
+    pub fn to_byte(self) -> u8 {
         match self {
             Self::LineFeed => b'\n',
             Self::Null => b'\0',
         }
     }
+
+    /// Reads a line without the line separator.
+    ///
+    /// Returns `Ok(None)` if the reader reached EOF.
+    fn read_line<R: BufRead>(self, reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        // Use `BufRead::has_data_left` once it is stabilized.
+        // See <https://github.com/rust-lang/rust/issues/86423>.
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut bytes = Vec::new();
+        reader.read_until(self.to_byte(), &mut bytes)?;
+        if bytes.last() == Some(&self.to_byte()) {
+            bytes.pop();
+        }
+
+        Ok(Some(bytes))
+    }
+}
+
+/// Format of the file to be edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditFormat {
+    /// A new filename per line.
+    ///
+    /// Lines correspond to the source entries by their positions.
+    Lines,
+    /// A source filename and a new filename separated by a TAB per line.
+    ///
+    /// Lines can be reordered or removed, and removed entries are not renamed.
+    Pairs,
+}
+
+impl EditFormat {
+    /// Creates an edit format value from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    pub fn try_from_cli_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "lines" => Ok(Self::Lines),
+            "pairs" => Ok(Self::Pairs),
+            s => Err(Error::UnknownValue {
+                kind: "edit format",
+                value: s.to_owned(),
+            }),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `EditFormat` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub fn cli_possible_values() -> &'static [&'static str] {
+        &["lines", "pairs"]
+    }
 }
 
+/// Operation to run for each source and destination pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// Renames the source to the destination.
+    Rename,
+    /// Copies the source to the destination, and keeps the source.
+    Copy,
+    /// Creates a symbolic link to the source at the destination, and keeps
+    /// the source.
+    Symlink {
+        /// Whether the link target is an absolute path.
+        ///
+        /// If false, the target is relative to the directory of the link.
+        absolute: bool,
+    },
+    /// Creates a hard link to the source at the destination, and keeps the
+    /// source.
+    Hardlink,
+}
+
+impl Action {
+    /// Returns true if the action creates links.
+    pub fn is_link(self) -> bool {
+        matches!(self, Self::Symlink { .. } | Self::Hardlink)
+    }
+
+    /// Returns the name of the action, used in summaries and plan files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rename => "rename",
+            Self::Copy => "copy",
+            Self::Symlink { absolute: false } => "symlink",
+            Self::Symlink { absolute: true } => "absolute_symlink",
+            Self::Hardlink => "hardlink",
+        }
+    }
+
+    /// Creates an action from the name returned by `as_str`.
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "rename" => Some(Self::Rename),
+            "copy" => Some(Self::Copy),
+            "symlink" => Some(Self::Symlink { absolute: false }),
+            "absolute_symlink" => Some(Self::Symlink { absolute: true }),
+            "hardlink" => Some(Self::Hardlink),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Action {
+    #[inline]
+    fn default() -> Self {
+        Self::Rename
+    }
+}
+
+/// Field separator of `EditFormat::Pairs`.
+const PAIR_SEPARATOR: u8 = b'\t';
+
 /// Setup of a bulk rename.
 #[derive(Debug, Clone)]
-pub(crate) struct RenameSetup {
+pub struct RenameSetup {
     /// Source directory.
     source_dir: PathBuf,
     /// Source entries.
     entries: Vec<OsString>,
+    /// Operation to run for the changed entries.
+    action: Action,
+    /// Whether the filenames in the directory are case-insensitive.
+    case_insensitive: bool,
+    /// How destinations colliding with existing files or other destinations
+    /// are resolved.
+    conflict_policy: ConflictPolicy,
 }
 
 impl RenameSetup {
     /// Creates a new `RenameSetup` for the given directory.
     #[inline]
-    pub(crate) fn new<P: Into<PathBuf>>(source_dir: P) -> anyhow::Result<Self> {
+    pub fn new<P: Into<PathBuf>>(source_dir: P) -> Result<Self, Error> {
         Self::new_impl(source_dir.into())
     }
 
     /// Creates a new `RenameSetup` for the given directory.
-    fn new_impl(source_dir: PathBuf) -> anyhow::Result<Self> {
+    fn new_impl(source_dir: PathBuf) -> Result<Self, Error> {
         // Get source filenames.
-        let mut entries = std::fs::read_dir(&source_dir)?
-            .map(|entry_res| entry_res.map(|entry| entry.file_name()))
-            .collect::<Result<Vec<_>, _>>()?;
+        let entries = std::fs::read_dir(&source_dir).and_then(|entries| {
+            entries
+                .map(|entry_res| entry_res.map(|entry| entry.file_name()))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let mut entries = match entries {
+            Ok(v) => v,
+            Err(source) => {
+                return Err(Error::FileIo {
+                    path: source_dir,
+                    source,
+                })
+            }
+        };
         entries.sort();
 
+        let case_insensitive = is_case_insensitive(&source_dir);
+        log::debug!("case insensitive: {}", case_insensitive);
+
         Ok(Self {
             source_dir,
             entries,
+            action: Action::Rename,
+            case_insensitive,
+            conflict_policy: ConflictPolicy::default(),
         })
     }
 
+    /// Sets the operation to run for the changed entries.
+    pub fn with_action(mut self, action: Action) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Sets whether the filenames in the directory are case-insensitive,
+    /// overriding the detected one.
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Sets how destinations colliding with existing files or other
+    /// destinations are resolved.
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Returns true if the filenames in the directory are case-insensitive.
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    /// Returns the key to compare the names, folded if case-insensitive.
+    fn name_key(&self, name: &OsStr) -> OsString {
+        if self.case_insensitive {
+            fold_case(name)
+        } else {
+            name.to_owned()
+        }
+    }
+
+    /// Returns the source directory.
+    pub fn source_dir(&self) -> &Path {
+        &self.source_dir
+    }
+
+    /// Returns the sorted filenames of the source entries.
+    pub fn entries(&self) -> &[OsString] {
+        &self.entries
+    }
+
     /// Writes the entries to a writer.
-    pub(crate) fn write<W: Write>(
+    pub fn write<W: Write>(
         &self,
         mut writer: W,
         escape: Escape,
         line_sep: LineSeparator,
-    ) -> anyhow::Result<()> {
+        format: EditFormat,
+    ) -> Result<(), Error> {
         for entry in &self.entries {
-            escape.escape(&mut writer, Path::new(entry), line_sep)?;
+            match format {
+                EditFormat::Lines => escape.escape(&mut writer, Path::new(entry), line_sep)?,
+                EditFormat::Pairs => {
+                    let mut escaped = Vec::new();
+                    escape.escape(&mut escaped, Path::new(entry), line_sep)?;
+                    if escaped.contains(&PAIR_SEPARATOR) {
+                        return Err(Error::Unescapable {
+                            name: entry.clone(),
+                            escape: escape.to_cli_str(),
+                            reason: "TAB character found, which is the separator of `pairs` format",
+                        });
+                    }
+                    writer.write_all(&escaped)?;
+                    writer.write_all(&[PAIR_SEPARATOR])?;
+                    writer.write_all(&escaped)?;
+                }
+            }
             write!(writer, "{}", line_sep.to_char())?;
         }
 
@@ -275,49 +548,246 @@ impl RenameSetup {
     }
 
     /// Creates a plan of a bulk rename.
-    pub(crate) fn plan<R: BufRead>(
+    ///
+    /// Every changed destination is checked by the given rules before the
+    /// plan is created, and all invalid destinations are reported at once.
+    pub fn plan<R: BufRead>(
         &self,
         reader: &mut R,
         escape: Escape,
         line_sep: LineSeparator,
-    ) -> anyhow::Result<RenamePlan> {
-        // A map from destination from source.
-        // This is reversed in order to detect duplicate destinations.
-        // Sources are guaranteed to be unique since they are filenames in a directory.
-        let mut rev_entries: HashMap<OsString, &OsString> = HashMap::new();
+        format: EditFormat,
+        name_check: NameCheck,
+    ) -> Result<RenamePlan, Error> {
+        let pairs = match format {
+            EditFormat::Lines => self.read_lines(reader, escape, line_sep)?,
+            EditFormat::Pairs => self.read_pairs(reader, escape, line_sep)?,
+        };
 
-        for source in &self.entries {
+        self.plan_pairs(pairs, name_check)
+    }
+
+    /// Creates a plan of a bulk rename from source and destination pairs,
+    /// such as rows of a mapping file.
+    ///
+    /// `rows` is a list of line numbers, sources, and destinations.
+    /// Entries not mentioned in `rows` are not renamed.
+    pub fn plan_rows(
+        &self,
+        rows: Vec<(usize, OsString, OsString)>,
+        name_check: NameCheck,
+    ) -> Result<RenamePlan, Error> {
+        let pairs = self.resolve_pairs(rows)?;
+
+        self.plan_pairs(pairs, name_check)
+    }
+
+    /// Reads destinations in `EditFormat::Lines` format.
+    ///
+    /// Returns line numbers, sources, and destinations of the changed entries.
+    fn read_lines<R: BufRead>(
+        &self,
+        reader: &mut R,
+        escape: Escape,
+        line_sep: LineSeparator,
+    ) -> Result<Vec<(usize, &OsString, OsString)>, Error> {
+        let mut pairs = Vec::with_capacity(self.entries.len());
+        for (index, source) in self.entries.iter().enumerate() {
             let dest = escape
                 .unescape_read_line(line_sep, reader)?
-                .ok_or_else(|| anyhow!("too few entries in the destination file list"))?;
+                .ok_or(Error::TooFewLines)?;
 
             if *source == dest {
                 log::debug!("source and dest is identical ({:?}). skipping.", source);
                 continue;
             }
+            // Line numbers are 1-based.
+            pairs.push((index + 1, source, dest));
+        }
+
+        Ok(pairs)
+    }
 
+    /// Reads sources and destinations in `EditFormat::Pairs` format.
+    ///
+    /// Returns line numbers, sources, and destinations of the changed entries.
+    fn read_pairs<R: BufRead>(
+        &self,
+        reader: &mut R,
+        escape: Escape,
+        line_sep: LineSeparator,
+    ) -> Result<Vec<(usize, &OsString, OsString)>, Error> {
+        let mut rows = Vec::new();
+        let mut lineno = 0;
+        while let Some(line) = line_sep.read_line(reader)? {
+            // Line numbers are 1-based.
+            lineno += 1;
+            if line.is_empty() {
+                continue;
+            }
+            let sep_pos = line
+                .iter()
+                .position(|&b| b == PAIR_SEPARATOR)
+                .ok_or(Error::MissingPairSeparator { lineno })?;
+            let source = escape.unescape(line[..sep_pos].to_vec());
+            let dest = escape.unescape(line[(sep_pos + 1)..].to_vec());
+            rows.push((lineno, source, dest));
+        }
+
+        self.resolve_pairs(rows)
+    }
+
+    /// Resolves the sources of the given pairs to the entries.
+    ///
+    /// `rows` is a list of line numbers, sources, and destinations.
+    /// Sources that do not exist or are specified more than once are
+    /// reported at once. Returns the changed entries.
+    fn resolve_pairs(
+        &self,
+        rows: Vec<(usize, OsString, OsString)>,
+    ) -> Result<Vec<(usize, &OsString, OsString)>, Error> {
+        // A map from a source to the line number where the source is found.
+        let mut seen: HashMap<&OsString, usize> = HashMap::new();
+        let mut problems = Vec::new();
+        let mut pairs = Vec::new();
+        for (lineno, source, dest) in rows {
+            let source = match self.entries.binary_search(&source) {
+                Ok(index) => &self.entries[index],
+                Err(_) => {
+                    problems.push(SourceProblem {
+                        lineno,
+                        source,
+                        prev_lineno: None,
+                    });
+                    continue;
+                }
+            };
+            if let Some(prev_lineno) = seen.insert(source, lineno) {
+                problems.push(SourceProblem {
+                    lineno,
+                    source: source.clone(),
+                    prev_lineno: Some(prev_lineno),
+                });
+                continue;
+            }
+
+            if *source == dest {
+                log::debug!("source and dest is identical ({:?}). skipping.", source);
+                continue;
+            }
+            pairs.push((lineno, source, dest));
+        }
+        if !problems.is_empty() {
+            return Err(Error::InvalidSources(problems));
+        }
+
+        Ok(pairs)
+    }
+
+    /// Creates a plan of a bulk rename from the changed entries.
+    ///
+    /// `pairs` is a list of line numbers, sources, and destinations.
+    fn plan_pairs(
+        &self,
+        pairs: Vec<(usize, &OsString, OsString)>,
+        name_check: NameCheck,
+    ) -> Result<RenamePlan, Error> {
+        NameChecker::new(&self.source_dir, name_check).check_all(
+            pairs
+                .iter()
+                .map(|(lineno, _source, dest)| (Location::Line(*lineno), dest.as_os_str())),
+        )?;
+        let ResolvedPairs {
+            pairs,
+            backups,
+            resolutions,
+        } = self.resolve_conflicts(pairs)?;
+
+        // A map from destination from source.
+        // This is reversed in order to detect duplicate destinations.
+        // Sources are guaranteed to be unique since they are filenames in a directory.
+        // This is ordered, so that the chains are planned deterministically.
+        let mut rev_entries: BTreeMap<OsString, &OsString> = BTreeMap::new();
+        // A map from destination to the line number where it is found.
+        let mut dest_linenos: HashMap<OsString, usize> = HashMap::new();
+        let mut collisions = Vec::new();
+
+        // A map from the case-folded (if necessary) destination to the line
+        // number and the source.
+        let mut dest_keys: HashMap<OsString, (usize, &OsString)> = HashMap::new();
+
+        for (lineno, source, dest) in pairs {
             log::debug!("new rename entry: source = {:?}, dest = {:?}", source, dest);
-            if let Some(another_source) = rev_entries.insert(dest.clone(), source) {
-                bail!(
-                    "Attempt to rename two files ({:?} and {:?}) to the same name {:?}",
-                    another_source,
-                    source,
-                    dest
-                );
+            let dest_key = self.name_key(&dest);
+            if let Some(&(another_lineno, another_source)) = dest_keys.get(&dest_key) {
+                collisions.push(Collision {
+                    lineno,
+                    source: source.clone(),
+                    other_lineno: another_lineno,
+                    other_source: another_source.clone(),
+                    dest,
+                });
+                continue;
             }
+            dest_keys.insert(dest_key, (lineno, source));
+            dest_linenos.insert(dest.clone(), lineno);
+            rev_entries.insert(dest, source);
+        }
+        if !collisions.is_empty() {
+            return Err(Error::Collisions(collisions));
+        }
+
+        if self.action != Action::Rename {
+            let mut plan = self.plan_new_names(rev_entries, dest_linenos, backups)?;
+            plan.conflicts = resolutions;
+            return Ok(plan);
+        }
+        // Backups are renamed away before the existing files are overwritten.
+        for (victim, backup) in &backups {
+            rev_entries.insert(backup.clone(), victim);
         }
 
         // Key is the last destination, the value is a chain from source to destination.
-        let mut seq_chains: HashMap<OsString, Vec<OsString>> = HashMap::new();
+        let mut seq_chains: BTreeMap<OsString, Vec<OsString>> = BTreeMap::new();
         let mut cyclic_chains: Vec<Vec<OsString>> = vec![];
 
+        // On case-insensitive filesystems, a destination differing from a
+        // source only in case is the same file as the source. Such
+        // destinations are replaced by the sources to link the chains, and
+        // the names to create are remembered.
+        let mut case_renames = BTreeMap::new();
+        if self.case_insensitive {
+            let sources_by_key = rev_entries
+                .values()
+                .map(|&source| (fold_case(source), source))
+                .collect::<HashMap<_, _>>();
+            for (dest, source) in std::mem::take(&mut rev_entries) {
+                match sources_by_key.get(&fold_case(&dest)) {
+                    Some(&slot) if *slot != dest => {
+                        log::debug!("case-only change of {:?}: {:?}", slot, dest);
+                        case_renames.insert(slot.clone(), dest);
+                        if slot == source {
+                            // Move through the temporary directory, as
+                            // renaming to itself may be a no-op.
+                            cyclic_chains.push(vec![source.clone()]);
+                        } else {
+                            rev_entries.insert(slot.clone(), source);
+                        }
+                    }
+                    _ => {
+                        rev_entries.insert(dest, source);
+                    }
+                }
+            }
+        }
+
         'collect_chains: loop {
             log::trace!(
                 "loop start: seq_chains = {:#?}, cyclic_chains = {:#?}",
                 seq_chains,
                 cyclic_chains
             );
-            // Take a random source-dest pair.
+            // Take the source-dest pair with the first destination.
             let dest = match rev_entries.keys().next().cloned() {
                 Some(v) => v,
                 None => break,
@@ -392,162 +862,1411 @@ impl RenameSetup {
         log::debug!("chains = {:#?}", seq_chains);
         log::debug!("cyclic chains = {:#?}", cyclic_chains);
 
-        // Use `seq_chains.into_values().collect()` once it is stabilized (at Rust 1.54.0).
-        // See <https://github.com/rust-lang/rust/issues/75294>.
-        Ok(RenamePlan {
+        let mut plan = RenamePlan {
             source_dir: self.source_dir.clone(),
-            seq_rename_chains: seq_chains.into_iter().map(|(_k, v)| v).collect(),
+            seq_rename_chains: seq_chains.into_iter().map(|(_, chain)| chain).collect(),
             cyclic_rename_chains: cyclic_chains,
+            case_renames,
+            action: Action::Rename,
+            new_names: Vec::new(),
+            cross_device: Vec::new(),
+            conflicts: resolutions,
+        };
+        plan.detect_cross_device();
+
+        Ok(plan)
+    }
+
+    /// Creates a plan of copies or links from the checked destination to
+    /// source map.
+    ///
+    /// Fails if a destination is the source of another copy, since the
+    /// result would depend on the order of the copies. Links never replace
+    /// existing files unless they are backed up, and hard links cannot be
+    /// made to directories.
+    ///
+    /// `backups` are pairs of existing files and their backup names, which
+    /// are renamed before the copies or links are created.
+    fn plan_new_names(
+        &self,
+        rev_entries: BTreeMap<OsString, &OsString>,
+        dest_linenos: HashMap<OsString, usize>,
+        backups: Vec<(OsString, OsString)>,
+    ) -> Result<RenamePlan, Error> {
+        if self.action.is_link() {
+            let mut problems = rev_entries
+                .keys()
+                .filter(|dest| backups.iter().all(|(victim, _)| victim != *dest))
+                .filter(|dest| fs::symlink_metadata(self.source_dir.join(dest)).is_ok())
+                .map(|dest| DestProblem {
+                    location: Location::Line(dest_linenos[dest]),
+                    dest: dest.clone(),
+                    problem: NameProblem::AlreadyExists,
+                })
+                .collect::<Vec<_>>();
+            if !problems.is_empty() {
+                problems.sort_by_key(|problem| problem.location);
+                return Err(Error::InvalidDestinations(problems));
+            }
+        }
+        if self.action == Action::Hardlink {
+            let mut dirs = rev_entries
+                .iter()
+                .filter(|(_, source)| self.source_dir.join(source).is_dir())
+                .map(|(dest, &source)| (dest_linenos[dest], source.clone()))
+                .collect::<Vec<_>>();
+            if !dirs.is_empty() {
+                dirs.sort();
+                return Err(Error::HardlinkedDirectories(dirs));
+            }
+        }
+
+        let source_linenos = rev_entries
+            .iter()
+            .map(|(dest, &source)| (self.name_key(source), dest_linenos[dest]))
+            .collect::<HashMap<_, _>>();
+        let mut conflicts = rev_entries
+            .keys()
+            .filter_map(|dest| {
+                source_linenos
+                    .get(&self.name_key(dest))
+                    .map(|&source_lineno| CopyConflict {
+                        lineno: dest_linenos[dest],
+                        dest: dest.clone(),
+                        source_lineno,
+                    })
+            })
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            conflicts.sort_by_key(|conflict| conflict.lineno);
+            return Err(Error::CopyConflicts(conflicts));
+        }
+
+        let mut new_names = rev_entries
+            .into_iter()
+            .map(|(dest, source)| (source.clone(), dest))
+            .collect::<Vec<_>>();
+        new_names.sort();
+        log::debug!("{} = {:#?}", self.action.as_str(), new_names);
+
+        Ok(RenamePlan {
+            source_dir: self.source_dir.clone(),
+            seq_rename_chains: backups
+                .into_iter()
+                .map(|(victim, backup)| vec![victim, backup])
+                .collect(),
+            cyclic_rename_chains: Vec::new(),
+            case_renames: BTreeMap::new(),
+            action: self.action,
+            new_names,
+            cross_device: Vec::new(),
+            conflicts: Vec::new(),
+        })
+    }
+
+    /// Resolves the destinations colliding with other destinations or
+    /// existing files by the conflict policy.
+    ///
+    /// With `ConflictPolicy::Error`, collisions of destinations are left to
+    /// the caller to report, and otherwise the destinations of existing
+    /// files are reported as invalid.
+    fn resolve_conflicts<'a>(
+        &self,
+        pairs: Vec<(usize, &'a OsString, OsString)>,
+    ) -> Result<ResolvedPairs<'a>, Error> {
+        let mut resolutions = Vec::new();
+        let resolution = |lineno, source: &OsString, dest, kind, resolution| ConflictResolution {
+            lineno,
+            source: source.clone(),
+            dest,
+            kind,
+            resolution,
+        };
+
+        // Destinations colliding with other destinations.
+        let mut kept: Vec<Option<(usize, &'a OsString, OsString)>> = Vec::new();
+        // A map from the destination key to the index of the kept pair.
+        let mut kept_indices: HashMap<OsString, usize> = HashMap::new();
+        let mut to_suffix = Vec::new();
+        let mut has_collisions = false;
+        for (lineno, source, dest) in pairs {
+            let dest_key = self.name_key(&dest);
+            let index = match kept_indices.get(&dest_key) {
+                Some(&index) => index,
+                None => {
+                    kept_indices.insert(dest_key, kept.len());
+                    kept.push(Some((lineno, source, dest)));
+                    continue;
+                }
+            };
+            let other_lineno = kept[index]
+                .as_ref()
+                .expect("should never fail: [consistency] indexed pairs are kept")
+                .0;
+            let kind = ConflictKind::Destination { other_lineno };
+            match &self.conflict_policy {
+                ConflictPolicy::Error => {
+                    has_collisions = true;
+                    kept.push(Some((lineno, source, dest)));
+                }
+                ConflictPolicy::Skip => {
+                    resolutions.push(resolution(lineno, source, dest, kind, Resolution::Skipped))
+                }
+                ConflictPolicy::Overwrite { .. } => {
+                    // The last one wins.
+                    let (other_lineno, other_source, other_dest) = kept[index]
+                        .take()
+                        .expect("should never fail: [consistency] indexed pairs are kept");
+                    resolutions.push(resolution(
+                        other_lineno,
+                        other_source,
+                        other_dest,
+                        ConflictKind::Destination {
+                            other_lineno: lineno,
+                        },
+                        Resolution::Superseded,
+                    ));
+                    kept_indices.insert(dest_key, kept.len());
+                    kept.push(Some((lineno, source, dest)));
+                }
+                ConflictPolicy::Suffix(_) => to_suffix.push((lineno, source, dest, kind)),
+            }
+        }
+        let mut dest_keys = kept_indices
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<HashSet<_>>();
+        let mut pairs = kept.into_iter().flatten().collect::<Vec<_>>();
+        for (lineno, source, dest, kind) in to_suffix {
+            let new_dest = self.free_name(&dest, &mut dest_keys);
+            pairs.push((lineno, source, new_dest.clone()));
+            resolutions.push(resolution(
+                lineno,
+                source,
+                dest,
+                kind,
+                Resolution::Suffixed { new_dest },
+            ));
+        }
+
+        // Destinations colliding with existing files.
+        // Skipping a rename leaves its source in place, so this is repeated
+        // until no more conflicts are found.
+        if has_collisions {
+            // Collisions are reported by the caller first.
+            return Ok(ResolvedPairs {
+                pairs,
+                backups: Vec::new(),
+                resolutions,
+            });
+        }
+        let mut backups = Vec::new();
+        loop {
+            // Sources renamed away are not conflicts. Destinations which are
+            // sources of other copies or links are reported by
+            // `plan_new_names` unless the policy resolves them.
+            let ignore_sources = self.action == Action::Rename
+                || matches!(
+                    self.conflict_policy,
+                    ConflictPolicy::Error | ConflictPolicy::Overwrite { .. }
+                );
+            let source_keys = if ignore_sources {
+                pairs
+                    .iter()
+                    .map(|(_, source, _)| self.name_key(source))
+                    .collect::<HashSet<_>>()
+            } else {
+                HashSet::new()
+            };
+            let (conflicts, rest): (Vec<_>, Vec<_>) =
+                pairs.into_iter().partition(|(_, _, dest)| {
+                    !source_keys.contains(&self.name_key(dest))
+                        && fs::symlink_metadata(self.source_dir.join(dest)).is_ok()
+                });
+            pairs = rest;
+            if conflicts.is_empty() {
+                break;
+            }
+            log::debug!("destinations of existing files: {:?}", conflicts);
+
+            match &self.conflict_policy {
+                ConflictPolicy::Error => {
+                    return Err(Error::InvalidDestinations(
+                        conflicts
+                            .into_iter()
+                            .map(|(lineno, _source, dest)| DestProblem {
+                                location: Location::Line(lineno),
+                                dest,
+                                problem: NameProblem::AlreadyExists,
+                            })
+                            .collect(),
+                    ));
+                }
+                ConflictPolicy::Skip => {
+                    resolutions.extend(conflicts.into_iter().map(|(lineno, source, dest)| {
+                        resolution(
+                            lineno,
+                            source,
+                            dest,
+                            ConflictKind::Existing,
+                            Resolution::Skipped,
+                        )
+                    }));
+                }
+                ConflictPolicy::Overwrite { backup } => {
+                    for (lineno, source, dest) in conflicts {
+                        let backup = if *backup {
+                            let backup = self.backup_name(&dest, &dest_keys);
+                            dest_keys.insert(self.name_key(&backup));
+                            backups.push((dest.clone(), backup.clone()));
+                            Some(backup)
+                        } else {
+                            None
+                        };
+                        resolutions.push(resolution(
+                            lineno,
+                            source,
+                            dest.clone(),
+                            ConflictKind::Existing,
+                            Resolution::Overwritten { backup },
+                        ));
+                        pairs.push((lineno, source, dest));
+                    }
+                    break;
+                }
+                ConflictPolicy::Suffix(_) => {
+                    for (lineno, source, dest) in conflicts {
+                        let new_dest = self.free_name(&dest, &mut dest_keys);
+                        resolutions.push(resolution(
+                            lineno,
+                            source,
+                            dest,
+                            ConflictKind::Existing,
+                            Resolution::Suffixed {
+                                new_dest: new_dest.clone(),
+                            },
+                        ));
+                        pairs.push((lineno, source, new_dest));
+                    }
+                    break;
+                }
+            }
+        }
+        pairs.sort_by_key(|(lineno, _, _)| *lineno);
+        resolutions.sort_by_key(|resolution| resolution.lineno);
+        log::debug!("conflict resolutions = {:#?}", resolutions);
+
+        Ok(ResolvedPairs {
+            pairs,
+            backups,
+            resolutions,
         })
     }
+
+    /// Returns a name made by the suffix template, which is neither an
+    /// existing file nor in the given destination keys, and adds it to the
+    /// keys.
+    fn free_name(&self, dest: &OsStr, dest_keys: &mut HashSet<OsString>) -> OsString {
+        let template = match &self.conflict_policy {
+            ConflictPolicy::Suffix(template) => template,
+            _ => unreachable!("free names are made only by the suffix policy"),
+        };
+        let new_dest = (1..)
+            .map(|n| template.apply(dest, n))
+            .find(|name| {
+                !dest_keys.contains(&self.name_key(name))
+                    && fs::symlink_metadata(self.source_dir.join(name)).is_err()
+            })
+            .expect("should never fail: [consistency] there are infinitely many candidates");
+        dest_keys.insert(self.name_key(&new_dest));
+
+        new_dest
+    }
+
+    /// Returns the name of the backup of the existing file, which is not in
+    /// the given destination keys.
+    ///
+    /// An existing file at the name is overwritten, as old backups are.
+    fn backup_name(&self, victim: &OsStr, dest_keys: &HashSet<OsString>) -> OsString {
+        let mut backup = victim.to_owned();
+        loop {
+            backup.push(BACKUP_SUFFIX);
+            if !dest_keys.contains(&self.name_key(&backup)) {
+                return backup;
+            }
+        }
+    }
+}
+
+/// Source and destination pairs with their conflicts resolved.
+#[derive(Debug)]
+struct ResolvedPairs<'a> {
+    /// Line numbers, sources, and destinations to plan.
+    pairs: Vec<(usize, &'a OsString, OsString)>,
+    /// Existing files and their backup names.
+    backups: Vec<(OsString, OsString)>,
+    /// Resolved conflicts.
+    resolutions: Vec<ConflictResolution>,
 }
 
 /// Plan of a bulk rename.
 #[derive(Debug, Clone)]
-pub(crate) struct RenamePlan {
+pub struct RenamePlan {
     /// Source directory.
     source_dir: PathBuf,
     /// Sequential (acyclic) rename chains.
     seq_rename_chains: Vec<Vec<OsString>>,
     /// Cyclic (looped) rename chains.
     cyclic_rename_chains: Vec<Vec<OsString>>,
+    /// Names to create instead of the names in the chains, when they differ
+    /// only in case on case-insensitive filesystems.
+    case_renames: BTreeMap<OsString, OsString>,
+    /// Action to create `new_names`.
+    action: Action,
+    /// Copies or links from sources to destinations.
+    new_names: Vec<(OsString, OsString)>,
+    /// Renames to destinations on other filesystems than the sources.
+    cross_device: Vec<(OsString, OsString)>,
+    /// Conflicts resolved by the conflict policy.
+    conflicts: Vec<ConflictResolution>,
 }
 
 impl RenamePlan {
+    /// Creates a plan from the rename chains and the new names created by
+    /// the action, such as ones loaded from a plan file.
+    ///
+    /// `case_renames` maps names in the chains to the names to create when
+    /// they are destinations, and is used for case-only changes on
+    /// case-insensitive filesystems. A cyclic chain of a single name is a
+    /// case-only rename of the name.
+    ///
+    /// Fails if the chains are inconsistent, i.e. a chain is too short or a
+    /// name appears more than once, if a case rename changes more than case,
+    /// or if a new name is used twice or touched by another operation.
+    pub fn from_chains(
+        source_dir: PathBuf,
+        seq_rename_chains: Vec<Vec<OsString>>,
+        cyclic_rename_chains: Vec<Vec<OsString>>,
+        case_renames: BTreeMap<OsString, OsString>,
+        action: Action,
+        new_names: Vec<(OsString, OsString)>,
+    ) -> Result<Self, Error> {
+        for (name, new_name) in &case_renames {
+            if fold_case(name) != fold_case(new_name) {
+                return Err(Error::InvalidChain {
+                    chain: vec![name.clone(), new_name.clone()],
+                    reason: "the case rename changes more than case",
+                });
+            }
+        }
+        let mut seen = HashSet::new();
+        for chain in seq_rename_chains.iter().chain(&cyclic_rename_chains) {
+            let is_case_only = chain.len() == 1
+                && cyclic_rename_chains.contains(chain)
+                && case_renames.contains_key(&chain[0]);
+            if chain.len() < 2 && !is_case_only {
+                return Err(Error::InvalidChain {
+                    chain: chain.clone(),
+                    reason: "less than two names",
+                });
+            }
+            if !chain.iter().all(|name| seen.insert(name)) {
+                return Err(Error::InvalidChain {
+                    chain: chain.clone(),
+                    reason: "a name appears more than once in the rename chains",
+                });
+            }
+        }
+        let new_name_sources = new_names.iter().map(|(src, _)| src).collect::<HashSet<_>>();
+        // New names may replace the first sources of the chains, such as
+        // backed up files.
+        let vacated = seq_rename_chains
+            .iter()
+            .map(|chain| &chain[0])
+            .collect::<HashSet<_>>();
+        let mut new_dests = HashSet::new();
+        for (src, dest) in &new_names {
+            if action == Action::Rename {
+                return Err(Error::InvalidChain {
+                    chain: vec![src.clone(), dest.clone()],
+                    reason: "a new name is given for the rename action",
+                });
+            }
+            let is_vacated = vacated.contains(dest);
+            if seen.contains(src)
+                || new_name_sources.contains(dest)
+                || (seen.contains(dest) && !is_vacated)
+                || !new_dests.insert(dest)
+            {
+                return Err(Error::InvalidChain {
+                    chain: vec![src.clone(), dest.clone()],
+                    reason: "the new name is touched by another operation",
+                });
+            }
+        }
+
+        Ok(Self {
+            source_dir,
+            seq_rename_chains,
+            cyclic_rename_chains,
+            case_renames,
+            action,
+            new_names,
+            cross_device: Vec::new(),
+            conflicts: Vec::new(),
+        })
+    }
+
+    /// Returns the source directory.
+    pub fn source_dir(&self) -> &Path {
+        &self.source_dir
+    }
+
+    /// Returns the sequential (acyclic) rename chains.
+    ///
+    /// Each chain is a list of names from the first source to the last destination.
+    pub fn seq_rename_chains(&self) -> &[Vec<OsString>] {
+        &self.seq_rename_chains
+    }
+
+    /// Returns the cyclic rename chains.
+    ///
+    /// The last name of each chain is renamed to the first name.
+    pub fn cyclic_rename_chains(&self) -> &[Vec<OsString>] {
+        &self.cyclic_rename_chains
+    }
+
+    /// Returns the names to create instead of the names in the chains, when
+    /// they differ only in case on case-insensitive filesystems.
+    pub fn case_renames(&self) -> &BTreeMap<OsString, OsString> {
+        &self.case_renames
+    }
+
+    /// Returns the name to create when the name in the chains is a destination.
+    fn dest_name<'a>(&'a self, name: &'a OsString) -> &'a OsString {
+        self.case_renames.get(name).unwrap_or(name)
+    }
+
+    /// Returns the action to create the new names.
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    /// Returns the copies or links to create, as sorted source and
+    /// destination pairs.
+    pub fn new_names(&self) -> &[(OsString, OsString)] {
+        &self.new_names
+    }
+
+    /// Returns the conflicts resolved by the conflict policy, sorted by the
+    /// line numbers.
+    ///
+    /// Plans loaded from files have no resolutions, as they are already
+    /// applied to the plans.
+    pub fn conflict_resolutions(&self) -> &[ConflictResolution] {
+        &self.conflicts
+    }
+
+    /// Returns the renames to destinations on other filesystems, as sorted
+    /// source and destination pairs.
+    ///
+    /// These are run as copy and delete, which is slower than renames and is
+    /// not atomic.
+    pub fn cross_device_renames(&self) -> &[(OsString, OsString)] {
+        &self.cross_device
+    }
+
+    /// Detects the renames to destinations on other filesystems than the
+    /// sources, by comparing the device IDs of the sources and the parent
+    /// directories of the destinations.
+    ///
+    /// Files that cannot be inspected are assumed to be on the same filesystem.
+    pub(crate) fn detect_cross_device(&mut self) {
+        let mut cross_device = Vec::new();
+        for (source, dest) in self.renames() {
+            let source_dev = match fs::symlink_metadata(self.source_dir.join(source)) {
+                Ok(metadata) => metadata.dev(),
+                Err(e) => {
+                    log::debug!("failed to get metadata of {:?}: {}", source, e);
+                    continue;
+                }
+            };
+            let dest_path = self.source_dir.join(dest);
+            let dest_parent = dest_path.parent().unwrap_or(&self.source_dir);
+            let dest_dev = match fs::metadata(dest_parent) {
+                Ok(metadata) => metadata.dev(),
+                Err(e) => {
+                    log::debug!("failed to get metadata of {:?}: {}", dest_parent, e);
+                    continue;
+                }
+            };
+            if source_dev != dest_dev {
+                log::debug!("cross-device rename: {:?} => {:?}", source, dest);
+                cross_device.push((source.to_owned(), dest.to_owned()));
+            }
+        }
+        self.cross_device = cross_device;
+    }
+
+    /// Returns true if the destination is on another filesystem than the source.
+    fn is_cross_device(&self, rel_dest: &Path) -> bool {
+        self.cross_device
+            .iter()
+            .any(|(_, dest)| Path::new(dest) == rel_dest)
+    }
+
+    /// Returns true if the plan renames nothing.
+    pub fn is_empty(&self) -> bool {
+        self.seq_rename_chains.is_empty()
+            && self.cyclic_rename_chains.is_empty()
+            && self.new_names.is_empty()
+    }
+
+    /// Returns the requested renames as source and destination pairs.
+    ///
+    /// Temporary renames to break cycles are not included.
+    pub fn renames(&self) -> Vec<(&OsStr, &OsStr)> {
+        let mut renames = Vec::new();
+        for chain in &self.seq_rename_chains {
+            renames.extend(
+                chain
+                    .windows(2)
+                    .map(|pair| (pair[0].as_os_str(), self.dest_name(&pair[1]).as_os_str())),
+            );
+        }
+        for chain in &self.cyclic_rename_chains {
+            renames.extend(
+                chain
+                    .iter()
+                    .zip(chain.iter().cycle().skip(1))
+                    .map(|(src, dest)| (src.as_os_str(), self.dest_name(dest).as_os_str())),
+            );
+        }
+        renames.sort();
+
+        renames
+    }
+
+    /// Returns the number of cyclic rename chains.
+    pub fn num_cycles(&self) -> usize {
+        self.cyclic_rename_chains.len()
+    }
+
+    /// Returns the warnings about the plan.
+    ///
+    /// Currently, this warns about existing files to be overwritten.
+    pub fn warnings(&self) -> Vec<String> {
+        self.overwritable_dests()
+            .filter(|dest| fs::symlink_metadata(self.source_dir.join(dest)).is_ok())
+            .map(|dest| format!("existing file {:?} will be overwritten", dest))
+            .collect()
+    }
+
+    /// Simulates the plan on an in-memory model of the directory, and checks
+    /// that it reaches the requested final state.
+    ///
+    /// Every requested rename should hold at the end, and no file should
+    /// disappear except existing files at destinations reported by
+    /// `warnings`. Failures indicate bugs of the planner, and are reported
+    /// as `Error::InternalPlanError`.
+    pub fn verify(&self) -> Result<(), Error> {
+        let mut memfs = MemoryFs::from_dir(&self.source_dir).map_err(|source| Error::FileIo {
+            path: self.source_dir.clone(),
+            source,
+        })?;
+        // Destinations in subdirectories are not loaded by `from_dir`.
+        for dest in self.overwritable_dests() {
+            if let Ok(metadata) = fs::symlink_metadata(self.source_dir.join(dest)) {
+                memfs.insert(PathBuf::from(dest), metadata.is_dir());
+            }
+        }
+        let before = memfs.entries().clone();
+        let overwritten = self
+            .overwritable_dests()
+            .filter_map(|dest| before.get(Path::new(dest)))
+            .map(|entry| entry.id)
+            .collect::<HashSet<_>>();
+
+        let mut reporter = Reporter::new(
+            OutputFormat::Null,
+            Box::new(io::sink()),
+            &self.source_dir,
+            true,
+        )?;
+        if let Err(e) = self.run(&mut memfs, &mut reporter) {
+            return Err(Error::InternalPlanError(vec![VerificationProblem::Failed(
+                e,
+            )]));
+        }
+
+        let mut problems = Vec::new();
+        for (source, dest) in self.renames() {
+            let source_id = before.get(Path::new(source)).map(|entry| entry.id);
+            let dest_id = memfs.get(Path::new(dest)).map(|entry| entry.id);
+            if source_id.is_none() || source_id != dest_id {
+                problems.push(VerificationProblem::NotRenamed {
+                    source: source.to_owned(),
+                    dest: dest.to_owned(),
+                });
+            }
+        }
+        for (source, dest) in &self.new_names {
+            let source_id = before.get(Path::new(source)).map(|entry| entry.id);
+            let old_dest_id = before.get(Path::new(dest)).map(|entry| entry.id);
+            let dest_id = memfs.get(Path::new(dest)).map(|entry| entry.id);
+            let created = match self.action {
+                // A hard link shares the identity with the source.
+                Action::Hardlink => dest_id == source_id,
+                _ => dest_id.is_some() && dest_id != old_dest_id,
+            };
+            if source_id.is_none() || !created {
+                problems.push(VerificationProblem::NotCreated {
+                    source: source.clone(),
+                    dest: dest.clone(),
+                });
+            }
+        }
+        let after = memfs
+            .entries()
+            .values()
+            .map(|entry| entry.id)
+            .collect::<HashSet<_>>();
+        for (name, entry) in &before {
+            if !after.contains(&entry.id) && !overwritten.contains(&entry.id) {
+                problems.push(VerificationProblem::Lost { name: name.clone() });
+            }
+        }
+        if !problems.is_empty() {
+            return Err(Error::InternalPlanError(problems));
+        }
+        log::debug!("the plan is verified by the simulation");
+
+        Ok(())
+    }
+
+    /// Checks that the plan would run to the end as far as possible without
+    /// modifying anything.
+    ///
+    /// This checks the existence of the sources, write permissions, read-only
+    /// mounts, sticky bits, and immutable and append-only attributes of the
+    /// directories and the files, and the lengths of the destinations.
+    /// All the blockers found are reported at once as
+    /// `Error::PreflightFailed`. Passing this does not guarantee the success,
+    /// as the files may change or the backend may fail for other reasons.
+    pub fn preflight(&self) -> Result<(), Error> {
+        preflight::check(self)
+    }
+
+    /// Returns the destinations which may already exist and be overwritten.
+    ///
+    /// New names replacing the first sources of the chains are not included,
+    /// as the sources are renamed away beforehand.
+    fn overwritable_dests(&self) -> impl Iterator<Item = &OsString> {
+        self.seq_rename_chains
+            .iter()
+            .filter_map(|chain| chain.last())
+            .chain(
+                self.new_names
+                    .iter()
+                    .map(|(_, dest)| dest)
+                    .filter(move |dest| {
+                        self.seq_rename_chains
+                            .iter()
+                            .all(|chain| chain.first() != Some(*dest))
+                    }),
+            )
+    }
+
+    /// Returns the destinations which exist now and will be overwritten.
+    fn existing_overwritable_dests(&self) -> HashSet<PathBuf> {
+        self.overwritable_dests()
+            .filter(|dest| fs::symlink_metadata(self.source_dir.join(dest)).is_ok())
+            .map(PathBuf::from)
+            .collect()
+    }
+
     /// Runs the rename plan.
-    pub(crate) fn run(self, renamer: &Renamer) -> io::Result<()> {
-        let source_dir: &Path = &self.source_dir;
+    ///
+    /// Each operation is reported to the reporter after it succeeded, and
+    /// the operation stopping the run is reported as a failure. The
+    /// reporter should be finished even if this fails.
+    pub fn run<R: Renamer + ?Sized>(
+        &self,
+        renamer: &mut R,
+        reporter: &mut Reporter,
+    ) -> io::Result<()> {
+        reporter.set_overwrites(self.existing_overwritable_dests());
         for seq_chain in &self.seq_rename_chains {
-            self.rename_seq_chain(seq_chain, renamer)?;
+            self.rename_seq_chain(seq_chain, renamer, reporter)?;
         }
         if !self.cyclic_rename_chains.is_empty() {
-            // Use `tempfile::TempDir::into_path()` in order to avoid user files
-            // to be removed by accident when I/O errors happened on rename.
-            // In other words, all we need here is just creating a temporary
-            // directory with unique name, but not automatically deleting
-            // temporary directory (on rename failure).
-            let tempdir_path = if renamer.is_dry_run() {
-                None
-            } else {
-                let path = tempfile::Builder::new()
-                    .prefix(".burne_")
-                    .tempdir_in(source_dir)?
-                    .into_path();
-                Some(path)
-            };
+            let tempdir_name = renamer.create_temp_dir(&self.source_dir)?;
+            reporter.report(&Operation::CreateTempDir {
+                path: &tempdir_name,
+            })?;
             for cyc_chain in &self.cyclic_rename_chains {
-                self.rename_cyc_chain(cyc_chain, tempdir_path.as_deref(), renamer)?;
+                self.rename_cyc_chain(cyc_chain, &tempdir_name, renamer, reporter)?;
             }
 
-            if let Some(tempdir_path) = tempdir_path {
-                // Remove the temporary directory.
-                // Note that the directory must be empty here.
-                fs::remove_dir(&tempdir_path)?;
+            // Remove the temporary directory.
+            // Note that the directory must be empty here.
+            let op = Operation::RemoveTempDir {
+                path: &tempdir_name,
+            };
+            if let Err(e) = renamer.remove_temp_dir(&self.source_dir, &tempdir_name) {
+                reporter.report_failure(&op, &e)?;
+                return Err(e);
             }
+            reporter.report(&op)?;
+        }
+        // Copies and links are created after the files at the destinations
+        // are backed up.
+        if !self.new_names.is_empty() {
+            self.create_new_names(renamer, reporter)?;
         }
 
         Ok(())
     }
 
+    /// Runs the rename plan, and continues after failures of independent
+    /// chains.
+    ///
+    /// Each sequential chain, cyclic chain, and copy or link is run
+    /// independently. When an operation of a chain fails, the operations of
+    /// the chain done so far are undone in reverse order, and the next chain
+    /// is run. Existing files overwritten by the chain are not restored. A
+    /// copy or link to a backed up file is skipped if the backup failed.
+    ///
+    /// Each operation (including undoing ones) is reported to the reporter
+    /// after it succeeded, and failed ones are reported as failures. Returns
+    /// the outcomes of all the chains, and fails only if the reporter fails.
+    pub fn run_keep_going<R: Renamer + ?Sized>(
+        &self,
+        renamer: &mut R,
+        reporter: &mut Reporter,
+    ) -> io::Result<Vec<ChainOutcome>> {
+        reporter.set_overwrites(self.existing_overwritable_dests());
+        let mut outcomes = Vec::new();
+        for seq_chain in &self.seq_rename_chains {
+            log::trace!("sequential chain: {:?}", seq_chain);
+            let renames = self.seq_chain_renames(seq_chain);
+            outcomes.push(ChainOutcome {
+                kind: ChainKind::Sequential,
+                names: seq_chain.clone(),
+                status: self.rename_all_or_undo(&renames, renamer, reporter)?,
+            });
+        }
+        if !self.cyclic_rename_chains.is_empty() {
+            match renamer.create_temp_dir(&self.source_dir) {
+                Ok(tempdir_name) => {
+                    reporter.report(&Operation::CreateTempDir {
+                        path: &tempdir_name,
+                    })?;
+                    for cyc_chain in &self.cyclic_rename_chains {
+                        log::trace!("cyclic chain: {:?}", cyc_chain);
+                        let renames = self.cyc_chain_renames(cyc_chain, &tempdir_name);
+                        outcomes.push(ChainOutcome {
+                            kind: ChainKind::Cyclic,
+                            names: cyc_chain.clone(),
+                            status: self.rename_all_or_undo(&renames, renamer, reporter)?,
+                        });
+                    }
+                    // The directory is not empty if undoing failed.
+                    let op = Operation::RemoveTempDir {
+                        path: &tempdir_name,
+                    };
+                    match renamer.remove_temp_dir(&self.source_dir, &tempdir_name) {
+                        Ok(()) => reporter.report(&op)?,
+                        Err(e) => {
+                            log::warn!(
+                                "failed to remove the temporary directory {:?}: {}",
+                                tempdir_name,
+                                e
+                            );
+                            reporter.report_failure(&op, &e)?;
+                        }
+                    }
+                }
+                Err(e) => {
+                    outcomes.extend(self.cyclic_rename_chains.iter().map(|cyc_chain| {
+                        ChainOutcome {
+                            kind: ChainKind::Cyclic,
+                            names: cyc_chain.clone(),
+                            status: ChainStatus::Failed {
+                                error: io::Error::new(
+                                    e.kind(),
+                                    format!("failed to create a temporary directory: {}", e),
+                                ),
+                                rollback_error: None,
+                            },
+                        }
+                    }));
+                }
+            }
+        }
+        if !self.new_names.is_empty() {
+            let failed_backups = outcomes
+                .iter()
+                .filter(|outcome| outcome.kind == ChainKind::Sequential && !outcome.is_done())
+                .filter_map(|outcome| outcome.names.first())
+                .cloned()
+                .collect::<HashSet<_>>();
+            let abs_source_dir = self.abs_source_dir();
+            for (src, dest) in &self.new_names {
+                let status = if failed_backups.contains(dest) {
+                    ChainStatus::Skipped
+                } else {
+                    let (src, dest) = (Path::new(src), Path::new(dest));
+                    let target = self.symlink_target(&abs_source_dir, src, dest);
+                    let op = self.new_name_op(src, dest, target.as_deref());
+                    match self.create_new_name(&op, renamer) {
+                        Ok(()) => {
+                            reporter.report(&op)?;
+                            ChainStatus::Done
+                        }
+                        Err(error) => {
+                            reporter.report_failure(&op, &error)?;
+                            ChainStatus::Failed {
+                                error,
+                                rollback_error: None,
+                            }
+                        }
+                    }
+                };
+                outcomes.push(ChainOutcome {
+                    kind: ChainKind::NewName,
+                    names: vec![src.clone(), dest.clone()],
+                    status,
+                });
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Runs the renames in order, and undoes the done ones in reverse order
+    /// if one fails.
+    ///
+    /// Fails only if the reporter fails.
+    fn rename_all_or_undo<R: Renamer + ?Sized>(
+        &self,
+        renames: &[(PathBuf, PathBuf)],
+        renamer: &mut R,
+        reporter: &mut Reporter,
+    ) -> io::Result<ChainStatus> {
+        for (i, (src, dest)) in renames.iter().enumerate() {
+            if let Err(error) = self.move_file(src, dest, self.is_cross_device(dest), renamer) {
+                log::debug!("failed to rename {:?} to {:?}: {}", src, dest, error);
+                reporter.report_failure(&Operation::Rename { src, dest }, &error)?;
+                for (src, dest) in renames[..i].iter().rev() {
+                    // Undo by the same method as done.
+                    if let Err(rollback_error) =
+                        self.move_file(dest, src, self.is_cross_device(dest), renamer)
+                    {
+                        log::debug!(
+                            "failed to undo renaming {:?} to {:?}: {}",
+                            src,
+                            dest,
+                            rollback_error
+                        );
+                        return Ok(ChainStatus::Failed {
+                            error,
+                            rollback_error: Some(rollback_error),
+                        });
+                    }
+                    reporter.report(&Operation::Rename {
+                        src: dest,
+                        dest: src,
+                    })?;
+                }
+                return Ok(ChainStatus::Failed {
+                    error,
+                    rollback_error: None,
+                });
+            }
+            reporter.report(&Operation::Rename { src, dest })?;
+        }
+
+        Ok(ChainStatus::Done)
+    }
+
+    /// Creates the copies or links.
+    fn create_new_names<R: Renamer + ?Sized>(
+        &self,
+        renamer: &mut R,
+        reporter: &mut Reporter,
+    ) -> io::Result<()> {
+        let abs_source_dir = self.abs_source_dir();
+        for (src, dest) in &self.new_names {
+            let (src, dest) = (Path::new(src), Path::new(dest));
+            let target = self.symlink_target(&abs_source_dir, src, dest);
+            let op = self.new_name_op(src, dest, target.as_deref());
+            if let Err(e) = self.create_new_name(&op, renamer) {
+                reporter.report_failure(&op, &e)?;
+                return Err(e);
+            }
+            reporter.report(&op)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the absolute path of the source directory.
+    fn abs_source_dir(&self) -> PathBuf {
+        // The source directory may not exist for in-memory backends.
+        self.source_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.source_dir.clone())
+    }
+
+    /// Returns the target of the symbolic link to create, if the action is
+    /// `Action::Symlink`.
+    fn symlink_target(&self, abs_source_dir: &Path, src: &Path, dest: &Path) -> Option<PathBuf> {
+        match self.action {
+            Action::Symlink { absolute: true } => Some(abs_source_dir.join(src)),
+            Action::Symlink { absolute: false } => {
+                Some(relative_symlink_target(abs_source_dir, src, dest))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the operation creating a copy or link.
+    fn new_name_op<'a>(
+        &self,
+        src: &'a Path,
+        dest: &'a Path,
+        target: Option<&'a Path>,
+    ) -> Operation<'a> {
+        match (self.action, target) {
+            (Action::Copy, _) => Operation::Copy { src, dest },
+            (Action::Symlink { .. }, Some(target)) => Operation::Symlink { src, target, dest },
+            (Action::Hardlink, _) => Operation::Hardlink { src, dest },
+            _ => unreachable!("symbolic links have targets, and rename plans have no new names"),
+        }
+    }
+
+    /// Creates a copy or link.
+    fn create_new_name<R: Renamer + ?Sized>(
+        &self,
+        op: &Operation<'_>,
+        renamer: &mut R,
+    ) -> io::Result<()> {
+        match *op {
+            Operation::Copy { src, dest } => renamer.copy(&self.source_dir, src, dest),
+            Operation::Symlink { target, dest, .. } => {
+                renamer.symlink(&self.source_dir, target, dest)
+            }
+            Operation::Hardlink { src, dest } => renamer.hard_link(&self.source_dir, src, dest),
+            _ => unreachable!("new names are created by copies or links"),
+        }
+    }
+
     /// Renames a file (or directory).
     ///
     /// `rel_src` and `rel_dest` should be relative to `self.soruce_dir`.
-    fn rename_single(
+    fn rename_single<R: Renamer + ?Sized>(
         &self,
         rel_src: impl AsRef<Path>,
         rel_dest: impl AsRef<Path>,
-        renamer: &Renamer,
+        renamer: &mut R,
+        reporter: &mut Reporter,
     ) -> io::Result<()> {
-        self.rename_single_impl(rel_src.as_ref(), rel_dest.as_ref(), renamer)
+        self.rename_single_impl(rel_src.as_ref(), rel_dest.as_ref(), renamer, reporter)
     }
 
     /// Renames a file (or directory).
     ///
     /// `rel_src` and `rel_dest` should be relative to `self.soruce_dir`.
-    fn rename_single_impl(
+    fn rename_single_impl<R: Renamer + ?Sized>(
         &self,
         rel_src: &Path,
         rel_dest: &Path,
-        renamer: &Renamer,
+        renamer: &mut R,
+        reporter: &mut Reporter,
     ) -> io::Result<()> {
-        renamer.rename(&self.source_dir, rel_src, rel_dest)
+        let op = Operation::Rename {
+            src: rel_src,
+            dest: rel_dest,
+        };
+        // Destinations on other filesystems are reached only from the
+        // sources or the temporary directory, both in the source directory.
+        if let Err(e) = self.move_file(rel_src, rel_dest, self.is_cross_device(rel_dest), renamer) {
+            reporter.report_failure(&op, &e)?;
+            return Err(e);
+        }
+        reporter.report(&op)
+    }
+
+    /// Renames a file (or directory), or moves it by copy and delete if
+    /// `cross_device` is true.
+    fn move_file<R: Renamer + ?Sized>(
+        &self,
+        rel_src: &Path,
+        rel_dest: &Path,
+        cross_device: bool,
+        renamer: &mut R,
+    ) -> io::Result<()> {
+        if cross_device {
+            renamer.move_across_devices(&self.source_dir, rel_src, rel_dest)
+        } else {
+            renamer.rename(&self.source_dir, rel_src, rel_dest)
+        }
     }
 
     /// Renames the given sequential chain using the given temporary directar
-    fn rename_seq_chain(&self, seq_chain: &[OsString], renamer: &Renamer) -> io::Result<()> {
+    fn rename_seq_chain<R: Renamer + ?Sized>(
+        &self,
+        seq_chain: &[OsString],
+        renamer: &mut R,
+        reporter: &mut Reporter,
+    ) -> io::Result<()> {
         log::trace!("sequential chain: {:?}", seq_chain);
-        for src_dest in seq_chain.windows(2).rev() {
-            let (src, dest) = match src_dest {
-                [src, dest] => (src, dest),
-                _ => unreachable!(
-                    "item type of `slice::windows(2)` iterator should always be 2-element arrays"
-                ),
-            };
-            self.rename_single(src, dest, renamer)?;
+        for (src, dest) in self.seq_chain_renames(seq_chain) {
+            self.rename_single(src, dest, renamer, reporter)?;
         }
 
         Ok(())
     }
 
+    /// Returns the renames of the given sequential chain in the order to run.
+    fn seq_chain_renames(&self, seq_chain: &[OsString]) -> Vec<(PathBuf, PathBuf)> {
+        seq_chain
+            .windows(2)
+            .rev()
+            .map(|src_dest| match src_dest {
+                [src, dest] => (PathBuf::from(src), PathBuf::from(self.dest_name(dest))),
+                _ => unreachable!(
+                    "item type of `slice::windows(2)` iterator should always be 2-element arrays"
+                ),
+            })
+            .collect()
+    }
+
     /// Runs the given cyclic chain using the given temporary directar
-    fn rename_cyc_chain(
+    ///
+    /// `tempdir_name` should be relative to `self.source_dir`.
+    fn rename_cyc_chain<R: Renamer + ?Sized>(
         &self,
         cyc_chain: &[OsString],
-        tempdir_path: Option<&Path>,
-        renamer: &Renamer,
+        tempdir_name: &Path,
+        renamer: &mut R,
+        reporter: &mut Reporter,
     ) -> io::Result<()> {
-        assert_eq!(tempdir_path.is_none(), renamer.is_dry_run());
-        let tempdir_path = tempdir_path.unwrap_or_else(|| Path::new("{{tempdir}}"));
         log::trace!("cyclic chain: {:?}", cyc_chain);
-        let chain_last = cyc_chain
-            .last()
-            .expect("should never fail: [consistency] chain has two or more elements");
-
-        // Break the chain.
-        let temp_moved = tempdir_path.join(chain_last);
-        log::trace!("rename: {:?} => {:?}", chain_last, temp_moved);
-        self.rename_single(&chain_last, &temp_moved, renamer)?;
+        for (src, dest) in self.cyc_chain_renames(cyc_chain, tempdir_name) {
+            self.rename_single(src, dest, renamer, reporter)?;
+        }
 
-        // Process the chain.
-        self.rename_seq_chain(cyc_chain, renamer)?;
+        Ok(())
+    }
 
-        // Complete the cycle.
+    /// Returns the renames of the given cyclic chain in the order to run.
+    ///
+    /// `tempdir_name` should be relative to `self.source_dir`.
+    fn cyc_chain_renames(
+        &self,
+        cyc_chain: &[OsString],
+        tempdir_name: &Path,
+    ) -> Vec<(PathBuf, PathBuf)> {
+        let chain_last = cyc_chain
+            .last()
+            .expect("should never fail: [consistency] chain is nonempty");
         let chain_first = cyc_chain
             .first()
-            .expect("should never fail: [consistency] chain has two or more elements");
-        self.rename_single(&temp_moved, &chain_first, renamer)?;
+            .expect("should never fail: [consistency] chain is nonempty");
 
-        Ok(())
+        // Break the chain, process the chain, and complete the cycle.
+        let temp_moved = tempdir_name.join(chain_last);
+        let mut renames = vec![(PathBuf::from(chain_last), temp_moved.clone())];
+        renames.extend(self.seq_chain_renames(cyc_chain));
+        renames.push((temp_moved, PathBuf::from(self.dest_name(chain_first))));
+
+        renames
     }
 }
 
-/// Renamer: an implementation to be used on rename.
-#[derive(Debug, Clone)]
-pub(crate) enum Renamer {
-    /// `std::fs`.
-    StdFs,
-    /// Dry-run.
-    DryRun,
+/// Returns the target of a symbolic link at `rel_dest` to `rel_src`, relative
+/// to the directory of the link.
+///
+/// Both paths are relative to the absolute source directory `base`, and the
+/// parent directory of `rel_dest` is resolved lexically.
+fn relative_symlink_target(base: &Path, rel_src: &Path, rel_dest: &Path) -> PathBuf {
+    let link_path = base.join(rel_dest);
+    let mut link_dir = Vec::new();
+    for component in link_path.parent().into_iter().flat_map(Path::components) {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                link_dir.pop();
+            }
+            component => link_dir.push(component),
+        }
+    }
+    let base = base.components().collect::<Vec<_>>();
+    let common = link_dir
+        .iter()
+        .zip(&base)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut target = PathBuf::new();
+    for _ in common..link_dir.len() {
+        target.push(Component::ParentDir);
+    }
+    for component in &base[common..] {
+        target.push(component);
+    }
+    target.push(rel_src);
+
+    target
 }
 
-impl Renamer {
-    /// Returns true if this is a dry-run renamer and does not need any temporary directories.
-    #[inline]
-    fn is_dry_run(&self) -> bool {
-        matches!(*self, Self::DryRun)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::memory_fs::MemoryEntry;
+    use crate::test_util::{plan_renames, run_plan};
+
+    /// Plan and the directory it is made in.
+    struct Fixture {
+        /// Source directory.
+        _dir: tempfile::TempDir,
+        /// Plan of the renames.
+        plan: RenamePlan,
     }
 
-    /// Renames the file at the given path.
-    fn rename(&self, source_dir: &Path, rel_src: &Path, rel_dest: &Path) -> io::Result<()> {
-        match *self {
-            Self::StdFs => {
-                log::trace!("rename: {:?} => {:?}", rel_src, rel_dest);
-                fs::rename(source_dir.join(rel_src), source_dir.join(rel_dest))
+    impl Fixture {
+        /// Creates the files in a temporary directory, and plans the renames.
+        fn new(files: &[&str], renames: &[(&str, &str)]) -> Self {
+            let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+            for file in files {
+                fs::write(dir.path().join(file), file).expect("failed to write a file");
             }
-            Self::DryRun => {
-                println!("{:?} => {:?}", rel_src, rel_dest);
-                Ok(())
+            let plan = plan_renames(dir.path(), renames);
+
+            Self { _dir: dir, plan }
+        }
+
+        /// Returns an in-memory filesystem with the files.
+        fn memfs(&self) -> MemoryFs {
+            MemoryFs::from_dir(self.plan.source_dir()).expect("failed to load the directory")
+        }
+
+        /// Runs the plan on the filesystem, and returns the result and the
+        /// reported operations as `op src dest` (or `op path`) strings.
+        fn run(&self, memfs: &mut MemoryFs) -> (io::Result<()>, Vec<String>) {
+            run_plan(&self.plan, memfs)
+        }
+    }
+
+    /// Returns the entries of the files.
+    fn files(memfs: &MemoryFs) -> BTreeMap<PathBuf, MemoryEntry> {
+        memfs.entries().clone()
+    }
+
+    /// Returns the identity of the entry at the path.
+    fn id_at(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &str) -> Option<u64> {
+        entries.get(Path::new(path)).map(|entry| entry.id)
+    }
+
+    #[test]
+    fn sequential_chain() {
+        let fixture = Fixture::new(&["a", "b"], &[("a", "b"), ("b", "c")]);
+        assert_eq!(fixture.plan.seq_rename_chains(), [["a", "b", "c"]]);
+        assert!(fixture.plan.cyclic_rename_chains().is_empty());
+
+        let mut memfs = fixture.memfs();
+        let before = files(&memfs);
+        let (result, ops) = fixture.run(&mut memfs);
+        result.expect("the plan should succeed");
+
+        // The last file is moved first, so that nothing is overwritten.
+        assert_eq!(ops, ["rename b c", "rename a b"]);
+        let after = files(&memfs);
+        assert_eq!(id_at(&after, "c"), id_at(&before, "b"));
+        assert_eq!(id_at(&after, "b"), id_at(&before, "a"));
+        assert_eq!(id_at(&after, "a"), None);
+    }
+
+    #[test]
+    fn two_cycle_through_temp_dir() {
+        let fixture = Fixture::new(&["a", "b"], &[("a", "b"), ("b", "a")]);
+        assert!(fixture.plan.seq_rename_chains().is_empty());
+        assert_eq!(fixture.plan.num_cycles(), 1);
+
+        let mut memfs = fixture.memfs();
+        let before = files(&memfs);
+        let (result, ops) = fixture.run(&mut memfs);
+        result.expect("the plan should succeed");
+
+        assert_eq!(
+            ops,
+            [
+                "create_temp_dir .burne_0",
+                "rename a .burne_0/a",
+                "rename b a",
+                "rename .burne_0/a b",
+                "remove_temp_dir .burne_0",
+            ]
+        );
+        let after = files(&memfs);
+        assert_eq!(id_at(&after, "a"), id_at(&before, "b"));
+        assert_eq!(id_at(&after, "b"), id_at(&before, "a"));
+        assert_eq!(
+            after.len(),
+            before.len(),
+            "the temporary directory should be removed"
+        );
+    }
+
+    #[test]
+    fn three_cycle_through_temp_dir() {
+        let fixture = Fixture::new(&["a", "b", "c"], &[("a", "b"), ("b", "c"), ("c", "a")]);
+        assert_eq!(fixture.plan.num_cycles(), 1);
+
+        let mut memfs = fixture.memfs();
+        let before = files(&memfs);
+        let (result, ops) = fixture.run(&mut memfs);
+        result.expect("the plan should succeed");
+
+        assert_eq!(ops.len(), 6);
+        assert_eq!(
+            ops.first().map(String::as_str),
+            Some("create_temp_dir .burne_0")
+        );
+        assert_eq!(
+            ops.last().map(String::as_str),
+            Some("remove_temp_dir .burne_0")
+        );
+        let after = files(&memfs);
+        assert_eq!(id_at(&after, "b"), id_at(&before, "a"));
+        assert_eq!(id_at(&after, "c"), id_at(&before, "b"));
+        assert_eq!(id_at(&after, "a"), id_at(&before, "c"));
+        assert_eq!(
+            after.len(),
+            before.len(),
+            "the temporary directory should be removed"
+        );
+    }
+
+    #[test]
+    fn chains_and_cycles_together() {
+        let fixture = Fixture::new(
+            &["a", "b", "x", "y", "z"],
+            &[("a", "b"), ("b", "c"), ("x", "y"), ("y", "x"), ("z", "w")],
+        );
+        assert_eq!(fixture.plan.seq_rename_chains().len(), 2);
+        assert_eq!(fixture.plan.num_cycles(), 1);
+
+        let mut memfs = fixture.memfs();
+        let before = files(&memfs);
+        fixture.run(&mut memfs).0.expect("the plan should succeed");
+
+        let after = files(&memfs);
+        for (source, dest) in fixture.plan.renames() {
+            let (source, dest) = (
+                source.to_str().expect("names are UTF-8"),
+                dest.to_str().expect("names are UTF-8"),
+            );
+            assert_eq!(
+                id_at(&after, dest),
+                id_at(&before, source),
+                "{} => {}",
+                source,
+                dest
+            );
+        }
+        assert_eq!(after.len(), before.len());
+    }
+
+    #[test]
+    fn injected_failure_stops_the_run() {
+        let fixture = Fixture::new(&["a", "b", "c"], &[("a", "b"), ("b", "c"), ("c", "a")]);
+        let num_ops = {
+            let mut memfs = fixture.memfs();
+            fixture.run(&mut memfs).0.expect("the plan should succeed");
+            memfs.num_ops()
+        };
+
+        for index in 0..num_ops {
+            let mut memfs = fixture.memfs();
+            let before = files(&memfs);
+            memfs.fail_at(index);
+            let (result, ops) = fixture.run(&mut memfs);
+
+            let error = result.expect_err("the injected failure should stop the run");
+            assert!(error.to_string().contains("injected"), "{}", error);
+            assert_eq!(
+                memfs.num_ops(),
+                index + 1,
+                "no operation should run after the failure"
+            );
+            // Operations done before the failure are reported, followed by
+            // the failed one. The temporary directory to be created (at
+            // index 0) has no name to report.
+            if index == 0 {
+                assert!(ops.is_empty(), "{:?}", ops);
+            } else {
+                assert_eq!(ops.len(), index + 1);
+                assert!(ops[index].contains("injected"), "{:?}", ops);
             }
+            // No file is lost, even if left in the temporary directory.
+            let before_ids = before
+                .values()
+                .map(|entry| entry.id)
+                .collect::<HashSet<_>>();
+            let after_ids = files(&memfs)
+                .values()
+                .map(|entry| entry.id)
+                .filter(|id| before_ids.contains(id))
+                .collect::<HashSet<_>>();
+            assert_eq!(after_ids, before_ids, "failure at {}", index);
         }
     }
+
+    #[test]
+    fn keep_going_rolls_back_the_failed_chain() {
+        let fixture = Fixture::new(
+            &["a", "b", "x", "y"],
+            &[("a", "b"), ("b", "c"), ("x", "y"), ("y", "x")],
+        );
+        let mut memfs = fixture.memfs();
+        let before = files(&memfs);
+        // The second rename of the sequential chain, which runs first.
+        memfs.fail_at(1);
+        let mut reporter = Reporter::new(
+            OutputFormat::Null,
+            Box::new(io::sink()),
+            fixture.plan.source_dir(),
+            false,
+        )
+        .expect("failed to create a reporter");
+        let outcomes = fixture
+            .plan
+            .run_keep_going(&mut memfs, &mut reporter)
+            .expect("the reporter should not fail");
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].kind, ChainKind::Sequential);
+        assert!(matches!(
+            outcomes[0].status,
+            ChainStatus::Failed {
+                rollback_error: None,
+                ..
+            }
+        ));
+        assert_eq!(outcomes[1].kind, ChainKind::Cyclic);
+        assert!(outcomes[1].is_done());
+
+        let after = files(&memfs);
+        // The failed chain is rolled back.
+        assert_eq!(id_at(&after, "a"), id_at(&before, "a"));
+        assert_eq!(id_at(&after, "b"), id_at(&before, "b"));
+        assert_eq!(id_at(&after, "c"), None);
+        // The independent cycle is done.
+        assert_eq!(id_at(&after, "x"), id_at(&before, "y"));
+        assert_eq!(id_at(&after, "y"), id_at(&before, "x"));
+    }
 }
@@ -0,0 +1,390 @@
+//! Reports of rename operations.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use crate::renamer::TEMPDIR_PREFIX;
+
+/// An operation run by a rename plan.
+///
+/// Paths are relative to the source directory.
+#[derive(Debug, Clone, Copy)]
+pub enum Operation<'a> {
+    /// Creates a temporary directory to break cyclic renames.
+    CreateTempDir {
+        /// Path of the temporary directory.
+        path: &'a Path,
+    },
+    /// Renames a file.
+    Rename {
+        /// Source path.
+        src: &'a Path,
+        /// Destination path.
+        dest: &'a Path,
+    },
+    /// Copies a file or a directory recursively.
+    Copy {
+        /// Source path.
+        src: &'a Path,
+        /// Destination path.
+        dest: &'a Path,
+    },
+    /// Creates a symbolic link.
+    Symlink {
+        /// Source path the link points to.
+        src: &'a Path,
+        /// Target of the link, relative to the directory of the link or absolute.
+        target: &'a Path,
+        /// Path of the link.
+        dest: &'a Path,
+    },
+    /// Creates a hard link.
+    Hardlink {
+        /// Source path.
+        src: &'a Path,
+        /// Path of the link.
+        dest: &'a Path,
+    },
+    /// Removes the temporary directory.
+    RemoveTempDir {
+        /// Path of the temporary directory.
+        path: &'a Path,
+    },
+}
+
+impl Operation<'_> {
+    /// Returns the JSON representation of the operation.
+    fn to_json(self) -> Value {
+        match self {
+            Self::CreateTempDir { path } => json!({
+                "op": "create_temp_dir",
+                "path": path_to_json(path),
+            }),
+            Self::Rename { src, dest } => json!({
+                "op": "rename",
+                "src": path_to_json(src),
+                "dest": path_to_json(dest),
+            }),
+            Self::Copy { src, dest } => json!({
+                "op": "copy",
+                "src": path_to_json(src),
+                "dest": path_to_json(dest),
+            }),
+            Self::Symlink { src, target, dest } => json!({
+                "op": "symlink",
+                "src": path_to_json(src),
+                "target": path_to_json(target),
+                "dest": path_to_json(dest),
+            }),
+            Self::Hardlink { src, dest } => json!({
+                "op": "hardlink",
+                "src": path_to_json(src),
+                "dest": path_to_json(dest),
+            }),
+            Self::RemoveTempDir { path } => json!({
+                "op": "remove_temp_dir",
+                "path": path_to_json(path),
+            }),
+        }
+    }
+}
+
+/// Returns the JSON representation of the path.
+///
+/// A path is represented as a string if it is valid UTF-8, or as
+/// `{"bytes": [...]}` otherwise.
+fn path_to_json(path: &Path) -> Value {
+    match path.to_str() {
+        Some(s) => Value::from(s),
+        None => json!({ "bytes": path.as_os_str().as_bytes() }),
+    }
+}
+
+/// Output format of reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable `"src" => "dest"` lines.
+    Text,
+    /// A JSON object with the list of the operations.
+    Json,
+    /// A JSON object per operation per line.
+    JsonLines,
+    /// POSIX shell script.
+    Shell,
+    /// No output.
+    Null,
+}
+
+impl OutputFormat {
+    /// Creates an output format value from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    pub fn try_from_cli_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            "sh" => Ok(Self::Shell),
+            "null" => Ok(Self::Null),
+            s => Err(Error::UnknownValue {
+                kind: "output format",
+                value: s.to_owned(),
+            }),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `OutputFormat` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub fn cli_possible_values() -> &'static [&'static str] {
+        &["text", "json", "jsonl", "sh", "null"]
+    }
+}
+
+/// Reporter of rename operations.
+pub struct Reporter {
+    /// Output format.
+    format: OutputFormat,
+    /// Output.
+    writer: Box<dyn Write>,
+    /// Source directory.
+    source_dir: PathBuf,
+    /// Whether the operations are dry-run.
+    dry_run: bool,
+    /// Operations collected for `OutputFormat::Json`.
+    json_ops: Vec<Value>,
+    /// Temporary directory for `OutputFormat::Shell`.
+    shell_tempdir: Option<PathBuf>,
+    /// Destinations replacing existing files, for `OutputFormat::Shell`.
+    overwrites: HashSet<PathBuf>,
+}
+
+impl std::fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reporter")
+            .field("format", &self.format)
+            .field("source_dir", &self.source_dir)
+            .field("dry_run", &self.dry_run)
+            .finish()
+    }
+}
+
+impl Reporter {
+    /// Creates a new reporter, and writes the header if necessary.
+    pub fn new(
+        format: OutputFormat,
+        mut writer: Box<dyn Write>,
+        source_dir: &Path,
+        dry_run: bool,
+    ) -> io::Result<Self> {
+        let source_dir = source_dir
+            .canonicalize()
+            .unwrap_or_else(|_| source_dir.to_owned());
+        if format == OutputFormat::Shell {
+            writer.write_all(b"#!/bin/sh\nset -eu\n")?;
+            // Checks if the path exists, including dangling symbolic links.
+            writer.write_all(b"exists() { [ -e \"$1\" ] || [ -L \"$1\" ]; }\n")?;
+            writer.write_all(b"cd -- ")?;
+            write_shell_quoted(&mut writer, source_dir.as_os_str().as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(Self {
+            format,
+            writer,
+            source_dir,
+            dry_run,
+            json_ops: Vec::new(),
+            shell_tempdir: None,
+            overwrites: HashSet::new(),
+        })
+    }
+
+    /// Sets the destinations replacing existing files.
+    ///
+    /// Shell scripts overwrite only these destinations, and stop before
+    /// overwriting other files.
+    pub(crate) fn set_overwrites(&mut self, dests: HashSet<PathBuf>) {
+        self.overwrites = dests;
+    }
+
+    /// Reports an operation.
+    pub fn report(&mut self, op: &Operation<'_>) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => match *op {
+                Operation::Rename { src, dest } => {
+                    writeln!(self.writer, "{:?} => {:?}", src, dest)?
+                }
+                Operation::Copy { src, dest } => {
+                    writeln!(self.writer, "{:?} => {:?} (copy)", src, dest)?
+                }
+                Operation::Symlink { target, dest, .. } => {
+                    writeln!(self.writer, "{:?} => {:?} (symlink)", target, dest)?
+                }
+                Operation::Hardlink { src, dest } => {
+                    writeln!(self.writer, "{:?} => {:?} (hardlink)", src, dest)?
+                }
+                _ => {}
+            },
+            OutputFormat::Json => self.json_ops.push(op.to_json()),
+            OutputFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &op.to_json())?;
+                self.writer.write_all(b"\n")?;
+            }
+            OutputFormat::Shell => self.report_shell(op, false)?,
+            OutputFormat::Null => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reports an operation that failed.
+    ///
+    /// JSON formats have the error message as `"error"` of the operation,
+    /// and the shell script has the command commented out, since the script
+    /// should replay only the operations done.
+    pub fn report_failure(&mut self, op: &Operation<'_>, error: &io::Error) -> io::Result<()> {
+        let json_with_error = || {
+            let mut value = op.to_json();
+            value["error"] = Value::from(error.to_string());
+            value
+        };
+        match self.format {
+            OutputFormat::Text => match *op {
+                Operation::CreateTempDir { path } | Operation::RemoveTempDir { path } => {
+                    writeln!(self.writer, "{:?}: failed: {}", path, error)?
+                }
+                Operation::Rename { src, dest }
+                | Operation::Copy { src, dest }
+                | Operation::Hardlink { src, dest } => {
+                    writeln!(self.writer, "{:?} => {:?}: failed: {}", src, dest, error)?
+                }
+                Operation::Symlink { target, dest, .. } => {
+                    writeln!(self.writer, "{:?} => {:?}: failed: {}", target, dest, error)?
+                }
+            },
+            OutputFormat::Json => self.json_ops.push(json_with_error()),
+            OutputFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &json_with_error())?;
+                self.writer.write_all(b"\n")?;
+            }
+            OutputFormat::Shell => {
+                // Write the failed command commented out.
+                let message = error.to_string().replace('\n', " ");
+                writeln!(self.writer, "# failed: {}", message)?;
+                self.report_shell(op, true)?;
+            }
+            OutputFormat::Null => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reports an operation as shell commands, commented out if `commented`
+    /// is true.
+    ///
+    /// Operations overwrite only the destinations set by `set_overwrites`,
+    /// and other operations are preceded by a check that stops the script if
+    /// the destination exists.
+    fn report_shell(&mut self, op: &Operation<'_>, commented: bool) -> io::Result<()> {
+        let (program, first_arg, dest) = match *op {
+            Operation::CreateTempDir { path } => {
+                // Create a new temporary directory rather than reusing the
+                // name, so that the script can be run anytime.
+                let command = format!("tmp=$(mktemp -d ./{}XXXXXX)", TEMPDIR_PREFIX);
+                self.shell_tempdir = Some(path.to_owned());
+                return self.write_shell_line(command.as_bytes(), commented);
+            }
+            Operation::RemoveTempDir { .. } => {
+                self.shell_tempdir = None;
+                return self.write_shell_line(b"rmdir -- \"$tmp\"", commented);
+            }
+            Operation::Rename { src, dest } => ("mv", self.shell_path(src)?, dest),
+            Operation::Copy { src, dest } => ("cp -Rp", self.shell_path(src)?, dest),
+            Operation::Symlink { target, dest, .. } => {
+                // The target is written as is, not relative to the source directory.
+                let mut target_arg = Vec::new();
+                write_shell_quoted(&mut target_arg, target.as_os_str().as_bytes())?;
+                ("ln -s", target_arg, dest)
+            }
+            Operation::Hardlink { src, dest } => ("ln", self.shell_path(src)?, dest),
+        };
+        let dest_arg = self.shell_path(dest)?;
+        let overwrite = self.overwrites.contains(dest);
+        if !overwrite {
+            let mut check = b"exists ".to_vec();
+            check.extend_from_slice(&dest_arg);
+            check.extend_from_slice(b" && exit 1");
+            self.write_shell_line(&check, commented)?;
+        }
+        let mut command = program.as_bytes().to_vec();
+        if overwrite {
+            command.extend_from_slice(b" -f");
+        }
+        command.extend_from_slice(b" -- ");
+        command.extend_from_slice(&first_arg);
+        command.push(b' ');
+        command.extend_from_slice(&dest_arg);
+        self.write_shell_line(&command, commented)
+    }
+
+    /// Writes a line of the shell script, commented out if `commented` is true.
+    fn write_shell_line(&mut self, line: &[u8], commented: bool) -> io::Result<()> {
+        if commented {
+            self.writer.write_all(b"# ")?;
+        }
+        self.writer.write_all(line)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Returns the path quoted for shell, using `$tmp` for the temporary directory.
+    fn shell_path(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut quoted = Vec::new();
+        let in_tempdir = self
+            .shell_tempdir
+            .as_deref()
+            .and_then(|tempdir| path.strip_prefix(tempdir).ok());
+        match in_tempdir {
+            Some(rel) => {
+                quoted.extend_from_slice(b"\"$tmp\"/");
+                write_shell_quoted(&mut quoted, rel.as_os_str().as_bytes())?;
+            }
+            None => write_shell_quoted(&mut quoted, path.as_os_str().as_bytes())?,
+        }
+
+        Ok(quoted)
+    }
+
+    /// Finishes the report.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            let doc = json!({
+                "source_dir": path_to_json(&self.source_dir),
+                "dry_run": self.dry_run,
+                "operations": self.json_ops,
+            });
+            serde_json::to_writer_pretty(&mut self.writer, &doc)?;
+            self.writer.write_all(b"\n")?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+/// Writes the bytes quoted by single quotes for POSIX shell.
+fn write_shell_quoted<W: Write>(mut writer: W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(b"'")?;
+    for (index, chunk) in bytes.split(|&b| b == b'\'').enumerate() {
+        if index != 0 {
+            writer.write_all(b"'\\''")?;
+        }
+        writer.write_all(chunk)?;
+    }
+    writer.write_all(b"'")
+}
@@ -0,0 +1,75 @@
+//! Helpers shared by the tests.
+
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use crate::backend::Renamer;
+use crate::name_check::NameCheck;
+use crate::renamer::{RenamePlan, RenameSetup};
+use crate::report::{OutputFormat, Reporter};
+
+/// Buffer shared with a reporter.
+#[derive(Debug, Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plans the renames of the files in the directory, given as source and
+/// destination pairs.
+pub(crate) fn plan_renames(dir: &Path, renames: &[(&str, &str)]) -> RenamePlan {
+    let rows = renames
+        .iter()
+        .enumerate()
+        .map(|(i, (src, dest))| (i + 1, OsString::from(src), OsString::from(dest)))
+        .collect();
+    RenameSetup::new(dir)
+        .expect("failed to read the directory")
+        .plan_rows(rows, NameCheck::Native)
+        .expect("failed to plan")
+}
+
+/// Runs the plan by the backend, and returns the result and the reported
+/// operations as `op src dest` (or `op path`) strings.
+pub(crate) fn run_plan<R: Renamer>(
+    plan: &RenamePlan,
+    renamer: &mut R,
+) -> (io::Result<()>, Vec<String>) {
+    let buf = SharedBuf::default();
+    let mut reporter = Reporter::new(
+        OutputFormat::JsonLines,
+        Box::new(buf.clone()),
+        plan.source_dir(),
+        false,
+    )
+    .expect("failed to create a reporter");
+    let result = plan.run(renamer, &mut reporter);
+    reporter.finish().expect("failed to finish the report");
+
+    let output = buf.0.borrow();
+    let ops = output
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let op = serde_json::from_slice::<Value>(line).expect("reports should be JSON");
+            ["op", "src", "dest", "path", "error"]
+                .iter()
+                .filter_map(|key| op[key].as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    (result, ops)
+}
@@ -0,0 +1,524 @@
+//! Non-interactive transforms of the edit buffer.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context as _};
+use burne::{EditFormat, Escape, LineSeparator};
+use regex::{Regex, RegexBuilder};
+
+/// Part of a filename a transform is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scope {
+    /// Whole filename.
+    Name,
+    /// Filename without the extension.
+    Stem,
+    /// Extension without the leading dot.
+    Extension,
+}
+
+impl Scope {
+    /// Applies the given function to the target part of the filename.
+    ///
+    /// The extension is the part after the last dot, as `Path::extension`
+    /// does. If the filename has no extension, `Scope::Stem` targets the
+    /// whole name and `Scope::Extension` targets nothing.
+    pub(crate) fn apply<F>(self, name: &str, f: F) -> String
+    where
+        F: FnOnce(&str) -> String,
+    {
+        match (self, split_extension(name)) {
+            (Self::Name, _) | (Self::Stem, (_, None)) => f(name),
+            (Self::Extension, (_, None)) => name.to_owned(),
+            (Self::Stem, (stem, Some(ext))) => format!("{}.{}", f(stem), ext),
+            (Self::Extension, (stem, Some(ext))) => format!("{}.{}", stem, f(ext)),
+        }
+    }
+}
+
+impl Scope {
+    /// Creates a scope value from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn try_from_cli_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "name" => Ok(Self::Name),
+            "stem" => Ok(Self::Stem),
+            "ext" => Ok(Self::Extension),
+            s => Err(anyhow!("unknown scope {:?}", s)),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `Scope` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn cli_possible_values() -> &'static [&'static str] {
+        &["name", "stem", "ext"]
+    }
+}
+
+/// Case conversion of filenames.
+///
+/// Letters are converted by the Unicode default mappings and the rules of
+/// the `CaseLocale`, so `ß` becomes `SS` in upper case and `Ss` at the start
+/// of a title-cased word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaseConversion {
+    /// `lower case`.
+    Lower,
+    /// `UPPER CASE`.
+    Upper,
+    /// `Title Case`, capitalizing every word.
+    Title,
+    /// `Sentence case`, capitalizing only the first word.
+    Sentence,
+    /// `snake_case`.
+    Snake,
+    /// `kebab-case`.
+    Kebab,
+    /// `camelCase`.
+    Camel,
+}
+
+impl CaseConversion {
+    /// Creates a case conversion value from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn try_from_cli_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "lower" => Ok(Self::Lower),
+            "upper" => Ok(Self::Upper),
+            "title" => Ok(Self::Title),
+            "sentence" => Ok(Self::Sentence),
+            "snake" => Ok(Self::Snake),
+            "kebab" => Ok(Self::Kebab),
+            "camel" => Ok(Self::Camel),
+            s => Err(anyhow!("unknown case {:?}", s)),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `CaseConversion` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn cli_possible_values() -> &'static [&'static str] {
+        &[
+            "lower", "upper", "title", "sentence", "snake", "kebab", "camel",
+        ]
+    }
+
+    /// Converts the case of the string.
+    ///
+    /// Words are runs of letters, digits, and combining marks. `title` and
+    /// `sentence` keep the characters between words as is. `snake`, `kebab`,
+    /// and `camel` also split words at case changes (`fileName`, `HTTPServer`),
+    /// drop the characters between words, and join the words by `_`, `-`, or
+    /// nothing respectively, converting each part between dots separately so
+    /// that the extensions and the leading dot of hidden files are kept.
+    pub(crate) fn apply(self, s: &str, locale: CaseLocale) -> String {
+        match self {
+            Self::Lower => locale.lowercase(s),
+            Self::Upper => locale.uppercase(s),
+            Self::Title | Self::Sentence => {
+                let mut converted = String::with_capacity(s.len());
+                let mut is_first = true;
+                for (is_word, run) in split_runs(s) {
+                    if !is_word {
+                        converted.push_str(run);
+                    } else if is_first || self == Self::Title {
+                        converted.push_str(&locale.capitalize(run));
+                    } else {
+                        converted.push_str(&locale.lowercase(run));
+                    }
+                    is_first &= !is_word;
+                }
+                converted
+            }
+            Self::Snake | Self::Kebab | Self::Camel => s
+                .split('.')
+                .map(|part| self.join_words(part, locale))
+                .collect::<Vec<_>>()
+                .join("."),
+        }
+    }
+
+    /// Splits the string into words, and joins them in the style of the
+    /// conversion.
+    fn join_words(self, s: &str, locale: CaseLocale) -> String {
+        let words = split_runs(s)
+            .filter(|&(is_word, _)| is_word)
+            .flat_map(|(_, run)| split_case_changes(run));
+        let mut converted = String::with_capacity(s.len());
+        for (i, word) in words.enumerate() {
+            match self {
+                Self::Snake if i != 0 => converted.push('_'),
+                Self::Kebab if i != 0 => converted.push('-'),
+                Self::Camel if i != 0 => {
+                    converted.push_str(&locale.capitalize(word));
+                    continue;
+                }
+                _ => {}
+            }
+            converted.push_str(&locale.lowercase(word));
+        }
+        converted
+    }
+}
+
+/// Returns true if the character is a part of a word.
+fn is_word_char(c: char) -> bool {
+    // `char::is_alphanumeric` does not cover combining marks, such as
+    // U+0301 of a decomposed `é`.
+    c.is_alphanumeric()
+        || matches!(
+            c,
+            '\u{0300}'..='\u{036F}'
+                | '\u{1AB0}'..='\u{1AFF}'
+                | '\u{1DC0}'..='\u{1DFF}'
+                | '\u{20D0}'..='\u{20FF}'
+                | '\u{FE20}'..='\u{FE2F}'
+        )
+}
+
+/// Splits the string into runs of word characters and runs of other
+/// characters, and returns them with whether they are words.
+fn split_runs(s: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let is_word = is_word_char(rest.chars().next()?);
+        let len = rest
+            .find(|c| is_word_char(c) != is_word)
+            .unwrap_or(rest.len());
+        let (run, after) = rest.split_at(len);
+        rest = after;
+        Some((is_word, run))
+    })
+}
+
+/// Splits the word at the changes from lower case (or digits) to upper
+/// case, and before the last upper case letter followed by a lower case
+/// letter, such as `file|Name` and `HTTP|Server`.
+fn split_case_changes(word: &str) -> Vec<&str> {
+    let chars = word.char_indices().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut start = 0;
+    for i in 1..chars.len() {
+        let (pos, c) = chars[i];
+        let prev = chars[i - 1].1;
+        let next = chars.get(i + 1).map(|&(_, next)| next);
+        let is_boundary = c.is_uppercase()
+            && ((prev.is_lowercase() || prev.is_numeric())
+                || (prev.is_uppercase() && next.map_or(false, char::is_lowercase)));
+        if is_boundary {
+            words.push(&word[start..pos]);
+            start = pos;
+        }
+    }
+    words.push(&word[start..]);
+    words
+}
+
+/// Language-specific rules of case conversions.
+///
+/// Only the rules of dotted and dotless i are supported, since others (such
+/// as of Lithuanian and Greek accents) need much larger tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaseLocale {
+    /// Unicode default mappings, such as `I` => `i` and `i` => `I`.
+    Default,
+    /// Turkish and Azerbaijani mappings of dotted and dotless i, such as
+    /// `I` => `ı` and `i` => `İ`.
+    Turkic,
+}
+
+impl CaseLocale {
+    /// Creates a case locale value from the given string.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn try_from_cli_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "default" => Ok(Self::Default),
+            "tr" | "az" => Ok(Self::Turkic),
+            s => Err(anyhow!("unknown case locale {:?}", s)),
+        }
+    }
+
+    /// Returns the possible CLI string representation of the `CaseLocale` variants.
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn cli_possible_values() -> &'static [&'static str] {
+        &["default", "tr", "az"]
+    }
+
+    /// Converts the string to lower case.
+    fn lowercase(self, s: &str) -> String {
+        match self {
+            Self::Default => s.to_lowercase(),
+            // `I` followed by a combining dot above is a decomposed `İ`.
+            Self::Turkic => s
+                .replace("I\u{0307}", "i")
+                .replace('\u{0130}', "i")
+                .replace('I', "\u{0131}")
+                .to_lowercase(),
+        }
+    }
+
+    /// Converts the string to upper case.
+    fn uppercase(self, s: &str) -> String {
+        match self {
+            Self::Default => s.to_uppercase(),
+            // The dotless `ı` becomes `I` by the default mapping.
+            Self::Turkic => s.replace('i', "\u{0130}").to_uppercase(),
+        }
+    }
+
+    /// Capitalizes the word, converting the first letter to title case and
+    /// the rest to lower case.
+    fn capitalize(self, word: &str) -> String {
+        let mut chars = word.chars();
+        let first = match chars.next() {
+            Some(v) => v,
+            None => return String::new(),
+        };
+        let mut capitalized = self.titlecase(first);
+        capitalized.push_str(&self.lowercase(chars.as_str()));
+        capitalized
+    }
+
+    /// Converts the character to title case.
+    fn titlecase(self, c: char) -> String {
+        match c {
+            'i' if self == Self::Turkic => '\u{0130}'.to_string(),
+            // Digraphs have dedicated title case forms.
+            '\u{01C4}'..='\u{01C6}' => '\u{01C5}'.to_string(),
+            '\u{01C7}'..='\u{01C9}' => '\u{01C8}'.to_string(),
+            '\u{01CA}'..='\u{01CC}' => '\u{01CB}'.to_string(),
+            '\u{01F1}'..='\u{01F3}' => '\u{01F2}'.to_string(),
+            // Otherwise, the first character of the upper case form followed
+            // by the lower case of the rest, such as `Ss` for `ß`.
+            c => {
+                let mut upper = c.to_uppercase();
+                let mut titlecased = upper.next().map(String::from).unwrap_or_default();
+                titlecased.push_str(&upper.collect::<String>().to_lowercase());
+                titlecased
+            }
+        }
+    }
+}
+
+/// Splits the filename into the stem and the extension.
+///
+/// A leading dot (of a hidden file) does not start an extension.
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rfind('.') {
+        Some(0) | None => (name, None),
+        Some(pos) => (&name[..pos], Some(&name[(pos + 1)..])),
+    }
+}
+
+/// Substitution expression, such as `s/IMG_(\d+)/photo-$1/`.
+#[derive(Debug, Clone)]
+pub(crate) struct SubstExpr {
+    /// Pattern.
+    pattern: Regex,
+    /// Replacement, in the syntax of `regex::Regex::replace`.
+    replacement: String,
+    /// Whether to replace all matches.
+    global: bool,
+}
+
+impl SubstExpr {
+    /// Parses a substitution expression in `s/PATTERN/REPLACEMENT/FLAGS` form.
+    ///
+    /// Any ASCII punctuation can be used as the delimiter instead of `/`,
+    /// and it can be escaped by a backslash in the pattern and the
+    /// replacement. The replacement can refer to capture groups by `$1`,
+    /// `${name}`, or `\1`, and any other `$` is a literal `$`. Available flags
+    /// are `g` (replace all matches) and `i` (case-insensitive match).
+    ///
+    /// This is intended for use with CLI parser.
+    pub(crate) fn try_from_cli_str(s: &str) -> anyhow::Result<Self> {
+        let rest = s
+            .strip_prefix('s')
+            .ok_or_else(|| anyhow!("substitution expression should start with `s`: {:?}", s))?;
+        let delim = rest
+            .chars()
+            .next()
+            .filter(|c| c.is_ascii_punctuation() && *c != '\\')
+            .ok_or_else(|| anyhow!("invalid delimiter in the substitution expression {:?}", s))?;
+        let rest = &rest[delim.len_utf8()..];
+
+        let (pattern, rest) = split_at_delimiter(rest, delim)
+            .ok_or_else(|| anyhow!("unterminated pattern in the expression {:?}", s))?;
+        let (replacement, flags) = split_at_delimiter(rest, delim)
+            .ok_or_else(|| anyhow!("unterminated replacement in the expression {:?}", s))?;
+
+        let mut builder = RegexBuilder::new(&pattern);
+        let mut global = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                c => bail!("unknown flag {:?} in the expression {:?}", c, s),
+            }
+        }
+        let pattern = builder
+            .build()
+            .with_context(|| format!("invalid pattern in the expression {:?}", s))?;
+
+        Ok(Self {
+            pattern,
+            replacement: convert_backrefs(&replacement),
+            global,
+        })
+    }
+
+    /// Applies the substitution to the given string.
+    pub(crate) fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let limit = if self.global { 0 } else { 1 };
+        self.pattern.replacen(s, limit, self.replacement.as_str())
+    }
+}
+
+/// Splits the string at the first unescaped delimiter, and unescapes the delimiter.
+///
+/// Returns `None` if the delimiter is not found.
+fn split_at_delimiter(s: &str, delim: char) -> Option<(String, &str)> {
+    let mut part = String::new();
+    let mut chars = s.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        if c == delim {
+            return Some((part, &s[(pos + c.len_utf8())..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delim => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            }
+            continue;
+        }
+        part.push(c);
+    }
+
+    None
+}
+
+/// Converts the replacement to the syntax of `regex` crate.
+///
+/// Sed-style backreferences (`\1`) and `$1` become `${1}`, and `${name}` is
+/// kept as is. Any other `$` (such as in `$HOME`), `$$`, and `\$` are a
+/// literal `$`.
+fn convert_backrefs(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(d) if d.is_ascii_digit() => push_group_number(&mut converted, &mut chars),
+                Some('\\') => {
+                    converted.push('\\');
+                    chars.next();
+                }
+                Some('$') => {
+                    converted.push_str("$$");
+                    chars.next();
+                }
+                _ => converted.push('\\'),
+            },
+            '$' => match chars.peek() {
+                Some(d) if d.is_ascii_digit() => push_group_number(&mut converted, &mut chars),
+                Some('{') => {
+                    let rest = chars.clone().skip(1).collect::<String>();
+                    let name = rest.split('}').next().filter(|name| {
+                        rest.len() != name.len()
+                            && !name.is_empty()
+                            && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+                    });
+                    match name {
+                        Some(name) => {
+                            converted.push_str("${");
+                            converted.push_str(name);
+                            converted.push('}');
+                            // Skip `{`, the name, and `}`.
+                            chars.nth(name.len() + 1);
+                        }
+                        None => converted.push_str("$$"),
+                    }
+                }
+                Some('$') => {
+                    converted.push_str("$$");
+                    chars.next();
+                }
+                _ => converted.push_str("$$"),
+            },
+            c => converted.push(c),
+        }
+    }
+
+    converted
+}
+
+/// Consumes the digits of a group number, and pushes it in `${1}` form.
+fn push_group_number<I>(converted: &mut String, chars: &mut std::iter::Peekable<I>)
+where
+    I: Iterator<Item = char>,
+{
+    converted.push_str("${");
+    while let Some(d) = chars.peek().copied().filter(char::is_ascii_digit) {
+        converted.push(d);
+        chars.next();
+    }
+    converted.push('}');
+}
+
+/// Applies the given function to the new filename of each line of the buffer.
+///
+/// The buffer should be the one written by `RenameSetup::write`. For
+/// `EditFormat::Pairs`, the source filename before the TAB is kept as is.
+/// Each filename is unescaped before the function is applied, and the result
+/// is escaped again, so that the function never sees escape sequences.
+pub(crate) fn transform_lines<F>(
+    buffer: &[u8],
+    escape: Escape,
+    line_sep: LineSeparator,
+    format: EditFormat,
+    mut f: F,
+) -> anyhow::Result<Vec<u8>>
+where
+    F: FnMut(&str) -> String,
+{
+    let sep = line_sep.to_byte();
+    let mut transformed = Vec::with_capacity(buffer.len());
+    // Every line is terminated by the separator, so the last empty piece
+    // after the last separator is not a line.
+    let num_lines = buffer.iter().filter(|&&b| b == sep).count();
+    for line in buffer.split(|&b| b == sep).take(num_lines) {
+        let dest = match (format, line.iter().position(|&b| b == b'\t')) {
+            (EditFormat::Pairs, Some(pos)) => {
+                let (source, dest) = line.split_at(pos + 1);
+                transformed.extend_from_slice(source);
+                dest
+            }
+            _ => line,
+        };
+        let name = escape
+            .unescape(dest.to_vec())
+            .into_string()
+            .map_err(|name| {
+                anyhow!(
+                    "the filename {:?} is not valid UTF-8 and cannot be transformed",
+                    name
+                )
+            })?;
+        escape.escape(&mut transformed, Path::new(&f(&name)), line_sep)?;
+        transformed.push(sep);
+    }
+
+    Ok(transformed)
+}